@@ -0,0 +1,25 @@
+use camera::Ray;
+use color::ColorVector;
+use integrator::Integrator;
+use tracer::RayTracer;
+
+// Grayscale ambient occlusion of the surfaces the camera sees, see RenderMode::AmbientOcclusion.
+#[derive(Debug, Default)]
+pub struct AmbientOcclusionIntegrator {}
+
+impl AmbientOcclusionIntegrator {
+  pub fn new() -> AmbientOcclusionIntegrator {
+    AmbientOcclusionIntegrator {}
+  }
+}
+
+impl Integrator for AmbientOcclusionIntegrator {
+  fn get_radiance(&self, tracer: &RayTracer, ray: &Ray) -> ColorVector {
+    let info = tracer.test_intersection(ray, 0);
+    if !info.is_hit || info.distance <= 0.0 {
+      return ColorVector::new(1.0, 1.0, 1.0);
+    }
+    let ao = tracer.get_ambient_occlusion(&info, ray);
+    ColorVector::new(ao, ao, ao)
+  }
+}
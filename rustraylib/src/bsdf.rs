@@ -0,0 +1,484 @@
+use std::f64;
+
+use color::ColorVector;
+use posvector::PosVector;
+use sampling;
+
+// below this roughness the ggx lobe is treated as a perfect mirror
+const MIN_ALPHA: f64 = 0.001;
+
+// A direction picked by importance sampling a bsdf.
+// weight is f * cos(theta_i) / pdf, i.e. what the incoming light along direction gets multiplied by.
+#[derive(Debug, Copy, Clone)]
+pub struct BsdfSample {
+  pub direction: PosVector,
+  pub weight: ColorVector,
+  pub pdf: f64,
+  pub is_specular: bool, // delta lobe, pdf is meaningless
+}
+
+// All directions are unit vectors pointing away from the surface.
+// wo points towards the viewer and wi towards the light, n is the shading normal.
+#[derive(Debug, Copy, Clone)]
+pub enum Lobe {
+  Lambert { albedo: ColorVector },
+  // lambertian base under a dielectric coat with reflectance f0. only the light the coat doesn't
+  // reflect, 1 - F, gets in and back out, so the base and the coat's ggx lobe add up to at most 1.
+  Coated { albedo: ColorVector, f0: ColorVector },
+  // microfacet reflection with the ggx distribution, smith masking and schlick fresnel.
+  // weight scales the whole lobe, e.g. for a partial clear coat.
+  Ggx { f0: ColorVector, alpha: f64, weight: f64 },
+}
+
+fn schlick(f0: ColorVector, cos_theta: f64) -> ColorVector {
+  let m = (1.0 - cos_theta).clamp(0.0, 1.0).powi(5);
+  f0.add(ColorVector::new(1.0 - f0.r, 1.0 - f0.g, 1.0 - f0.b).multiply_by_scalar(m))
+}
+
+// albedo times the fraction of light that gets through the coat on the way in and on the way out
+fn get_transmitted(albedo: ColorVector, f0: ColorVector, n_dot_o: f64, n_dot_i: f64) -> ColorVector {
+  let f_o = schlick(f0, n_dot_o);
+  let f_i = schlick(f0, n_dot_i);
+  ColorVector::new(
+    albedo.r * (1.0 - f_o.r) * (1.0 - f_i.r),
+    albedo.g * (1.0 - f_o.g) * (1.0 - f_i.g),
+    albedo.b * (1.0 - f_o.b) * (1.0 - f_i.b),
+  )
+}
+
+fn ggx_d(n_dot_h: f64, alpha: f64) -> f64 {
+  let a2 = alpha * alpha;
+  let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+  a2 / (f64::consts::PI * d * d)
+}
+
+fn smith_g1(n_dot_v: f64, alpha: f64) -> f64 {
+  let a2 = alpha * alpha;
+  2.0 * n_dot_v / (n_dot_v + (a2 + (1.0 - a2) * n_dot_v * n_dot_v).sqrt())
+}
+
+fn reflect(v: PosVector, n: PosVector) -> PosVector {
+  n.multiply_by_scalar(2.0 * v.dot_product(n)).subtract(v)
+}
+
+impl Lobe {
+  pub fn is_specular(&self) -> bool {
+    match *self {
+      Lobe::Lambert { .. } | Lobe::Coated { .. } => false,
+      Lobe::Ggx { alpha, .. } => alpha < MIN_ALPHA,
+    }
+  }
+
+  // rough estimate of how much energy the lobe reflects, used to pick lobes to sample
+  pub fn get_albedo(&self) -> f64 {
+    let c = match *self {
+      Lobe::Lambert { albedo } | Lobe::Coated { albedo, .. } => albedo,
+      Lobe::Ggx { f0, .. } => f0,
+    };
+    let avg = (c.r + c.g + c.b) / 3.0;
+    match *self {
+      Lobe::Lambert { .. } | Lobe::Coated { .. } => avg,
+      // even a black f0 reflects strongly at grazing angles
+      Lobe::Ggx { weight, .. } => avg.max(0.1) * weight,
+    }
+  }
+
+  // the same lobe reflecting only a fraction of the light
+  pub fn scaled(&self, factor: f64) -> Lobe {
+    match *self {
+      Lobe::Lambert { albedo } => Lobe::Lambert {
+        albedo: albedo.multiply_by_scalar(factor),
+      },
+      Lobe::Coated { albedo, f0 } => Lobe::Coated {
+        albedo: albedo.multiply_by_scalar(factor),
+        f0,
+      },
+      Lobe::Ggx { f0, alpha, weight } => Lobe::Ggx {
+        f0,
+        alpha,
+        weight: weight * factor,
+      },
+    }
+  }
+
+  pub fn eval(&self, n: PosVector, wo: PosVector, wi: PosVector) -> ColorVector {
+    let n_dot_o = n.dot_product(wo);
+    let n_dot_i = n.dot_product(wi);
+    if n_dot_o <= 0.0 || n_dot_i <= 0.0 {
+      return ColorVector::new(0.0, 0.0, 0.0);
+    }
+    match *self {
+      Lobe::Lambert { albedo } => albedo.multiply_by_scalar(1.0 / f64::consts::PI),
+      Lobe::Coated { albedo, f0 } => {
+        get_transmitted(albedo, f0, n_dot_o, n_dot_i).multiply_by_scalar(1.0 / f64::consts::PI)
+      }
+      Lobe::Ggx { f0, alpha, weight } => {
+        if alpha < MIN_ALPHA {
+          return ColorVector::new(0.0, 0.0, 0.0);
+        }
+        let h = wo.add(wi).normalize();
+        let d = ggx_d(n.dot_product(h), alpha);
+        let g = smith_g1(n_dot_o, alpha) * smith_g1(n_dot_i, alpha);
+        let f = schlick(f0, wi.dot_product(h));
+        f.multiply_by_scalar(weight * d * g / (4.0 * n_dot_o * n_dot_i))
+      }
+    }
+  }
+
+  pub fn pdf(&self, n: PosVector, wo: PosVector, wi: PosVector) -> f64 {
+    let n_dot_i = n.dot_product(wi);
+    if n.dot_product(wo) <= 0.0 || n_dot_i <= 0.0 {
+      return 0.0;
+    }
+    match *self {
+      Lobe::Lambert { .. } | Lobe::Coated { .. } => n_dot_i / f64::consts::PI,
+      Lobe::Ggx { alpha, .. } => {
+        if alpha < MIN_ALPHA {
+          return 0.0;
+        }
+        let h = wo.add(wi).normalize();
+        let n_dot_h = n.dot_product(h);
+        ggx_d(n_dot_h, alpha) * n_dot_h / (4.0 * wo.dot_product(h))
+      }
+    }
+  }
+
+  pub fn sample(&self, n: PosVector, wo: PosVector, u1: f64, u2: f64) -> Option<BsdfSample> {
+    let n_dot_o = n.dot_product(wo);
+    if n_dot_o <= 0.0 {
+      return None;
+    }
+    match *self {
+      Lobe::Lambert { albedo } => {
+        let wi = sampling::to_world(sampling::cosine_sample_hemisphere(u1, u2), n);
+        Some(BsdfSample {
+          direction: wi,
+          weight: albedo,
+          pdf: n.dot_product(wi) / f64::consts::PI,
+          is_specular: false,
+        })
+      }
+      Lobe::Coated { albedo, f0 } => {
+        let wi = sampling::to_world(sampling::cosine_sample_hemisphere(u1, u2), n);
+        let n_dot_i = n.dot_product(wi);
+        if n_dot_i <= 0.0 {
+          return None;
+        }
+        Some(BsdfSample {
+          direction: wi,
+          weight: get_transmitted(albedo, f0, n_dot_o, n_dot_i),
+          pdf: n_dot_i / f64::consts::PI,
+          is_specular: false,
+        })
+      }
+      Lobe::Ggx { f0, alpha, weight } => {
+        if alpha < MIN_ALPHA {
+          let wi = reflect(wo, n);
+          return Some(BsdfSample {
+            direction: wi,
+            weight: schlick(f0, n_dot_o).multiply_by_scalar(weight),
+            pdf: 1.0,
+            is_specular: true,
+          });
+        }
+
+        // sample a microfacet normal from D(h) * cos(theta_h) and reflect wo about it
+        let tan2_theta = alpha * alpha * u1 / (1.0 - u1).max(1e-12);
+        let cos_theta = 1.0 / (1.0 + tan2_theta).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * f64::consts::PI * u2;
+        let h = sampling::to_world(
+          PosVector::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta),
+          n,
+        );
+        let o_dot_h = wo.dot_product(h);
+        if o_dot_h <= 0.0 {
+          return None;
+        }
+        let wi = reflect(wo, h);
+        let n_dot_i = n.dot_product(wi);
+        if n_dot_i <= 0.0 {
+          return None;
+        }
+
+        let g = smith_g1(n_dot_o, alpha) * smith_g1(n_dot_i, alpha);
+        let f = schlick(f0, wi.dot_product(h));
+        Some(BsdfSample {
+          direction: wi,
+          weight: f.multiply_by_scalar(weight * g * o_dot_h / (n_dot_o * cos_theta)),
+          pdf: ggx_d(cos_theta, alpha) * cos_theta / (4.0 * o_dot_h),
+          is_specular: false,
+        })
+      }
+    }
+  }
+}
+
+// The reflectance of a surface point as a sum of lobes.
+#[derive(Debug, Clone)]
+pub struct Bsdf {
+  pub lobes: Vec<Lobe>,
+}
+
+impl Bsdf {
+  pub fn new(lobes: Vec<Lobe>) -> Bsdf {
+    Bsdf { lobes }
+  }
+
+  // stand in for the non physically based materials where a bsdf is needed: a lambertian base of
+  // color * diffuse under a plastic like coat, as rough as the phong shininess suggests and as
+  // strong as specular.
+  pub fn new_phong(color: ColorVector, diffuse: f64, specular: f64, shininess: f64) -> Bsdf {
+    let mut lobes = vec![Lobe::Lambert {
+      albedo: color.multiply_by_scalar(diffuse),
+    }];
+    if specular > 0.0 {
+      lobes.push(Lobe::Ggx {
+        f0: ColorVector::new(0.04, 0.04, 0.04),
+        alpha: (2.0 / (shininess.max(0.0) + 2.0)).sqrt(),
+        weight: specular,
+      });
+    }
+    Bsdf::new(lobes)
+  }
+
+  // adds the lobes of another bsdf, scaled by factor, e.g. to blend the bsdfs of two materials
+  pub fn add_scaled(&mut self, other: &Bsdf, factor: f64) {
+    if factor > 0.0 {
+      self.lobes.extend(other.lobes.iter().map(|lobe| lobe.scaled(factor)));
+    }
+  }
+
+  fn get_selection_weights(&self) -> (Vec<f64>, f64) {
+    let weights: Vec<f64> = self.lobes.iter().map(|lobe| lobe.get_albedo()).collect();
+    let total = weights.iter().sum();
+    (weights, total)
+  }
+
+  pub fn eval(&self, n: PosVector, wo: PosVector, wi: PosVector) -> ColorVector {
+    let mut f = ColorVector::new(0.0, 0.0, 0.0);
+    for lobe in &self.lobes {
+      f = f.add(lobe.eval(n, wo, wi));
+    }
+    f
+  }
+
+  // pdf of sample() choosing wi
+  pub fn pdf(&self, n: PosVector, wo: PosVector, wi: PosVector) -> f64 {
+    let (weights, total) = self.get_selection_weights();
+    if total <= 0.0 {
+      return 0.0;
+    }
+    let mut pdf = 0.0;
+    for (lobe, weight) in self.lobes.iter().zip(weights.iter()) {
+      pdf += weight / total * lobe.pdf(n, wo, wi);
+    }
+    pdf
+  }
+
+  // picks a lobe in proportion to its albedo, then samples it.
+  // the returned weight accounts for all the lobes, not just the one that was picked.
+  pub fn sample(&self, n: PosVector, wo: PosVector, u_lobe: f64, u1: f64, u2: f64) -> Option<BsdfSample> {
+    let (weights, total) = self.get_selection_weights();
+    if total <= 0.0 {
+      return None;
+    }
+
+    let mut target = u_lobe * total;
+    let mut chosen = self.lobes.len() - 1;
+    for (i, weight) in weights.iter().enumerate() {
+      if target < *weight {
+        chosen = i;
+        break;
+      }
+      target -= weight;
+    }
+
+    let lobe_probability = weights[chosen] / total;
+    let mut sample = self.lobes[chosen].sample(n, wo, u1, u2)?;
+
+    if sample.is_specular {
+      sample.weight = sample.weight.multiply_by_scalar(1.0 / lobe_probability);
+      sample.pdf = lobe_probability;
+      return Some(sample);
+    }
+
+    let pdf = self.pdf(n, wo, sample.direction);
+    if pdf <= 0.0 {
+      return None;
+    }
+    let f = self.eval(n, wo, sample.direction);
+    sample.weight = f.multiply_by_scalar(n.dot_product(sample.direction) / pdf);
+    sample.pdf = pdf;
+    Some(sample)
+  }
+
+  // only the glossy and mirror part of the bsdf, which whitted style tracing follows with secondary rays
+  pub fn get_specular_lobes(&self) -> Vec<Lobe> {
+    self
+      .lobes
+      .iter()
+      .filter(|lobe| matches!(**lobe, Lobe::Ggx { .. }))
+      .cloned()
+      .collect()
+  }
+
+  // the rest of the bsdf, for light that reaches the surface directly rather than through secondary rays
+  pub fn get_diffuse_lobes(&self) -> Vec<Lobe> {
+    self
+      .lobes
+      .iter()
+      .filter(|lobe| matches!(**lobe, Lobe::Lambert { .. } | Lobe::Coated { .. }))
+      .cloned()
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use material::{Material, PbrMaterial, SurfaceCoords};
+
+  const GRID: u32 = 256;
+
+  fn get_bsdfs() -> Vec<Bsdf> {
+    let white = ColorVector::new(1.0, 1.0, 1.0);
+    let ggx = |alpha| Bsdf::new(vec![Lobe::Ggx { f0: white, alpha, weight: 1.0 }]);
+    vec![
+      Bsdf::new(vec![Lobe::Lambert { albedo: white }]),
+      ggx(0.2),
+      ggx(0.5),
+      ggx(1.0),
+      Bsdf::new_phong(ColorVector::new(0.9, 0.9, 0.9), 1.0, 1.0, 20.0),
+    ]
+  }
+
+  // unit vector at theta from the normal (+z)
+  fn get_direction(theta: f64) -> PosVector {
+    PosVector::new(theta.sin(), 0.0, theta.cos())
+  }
+
+  // deterministic stratified numbers in [0, 1)
+  fn get_grid(i: u32, j: u32) -> (f64, f64) {
+    ((i as f64 + 0.5) / GRID as f64, (j as f64 + 0.5) / GRID as f64)
+  }
+
+  // the mean of the sample weights is the albedo, f * cos integrated over the hemisphere
+  fn get_albedo(bsdf: &Bsdf, n: PosVector, wo: PosVector) -> ColorVector {
+    let mut albedo = ColorVector::new(0.0, 0.0, 0.0);
+    for i in 0..GRID {
+      for j in 0..GRID {
+        let (u1, u2) = get_grid(i, j);
+        let u_lobe = ((i * GRID + j) as f64 + 0.5) / (GRID * GRID) as f64;
+        if let Some(sample) = bsdf.sample(n, wo, u_lobe, u1, u2) {
+          albedo = albedo.add(sample.weight);
+        }
+      }
+    }
+    albedo.multiply_by_scalar(1.0 / (GRID * GRID) as f64)
+  }
+
+  #[test]
+  fn bsdf_conserves_energy() {
+    let n = PosVector::new_unit_z();
+    for bsdf in get_bsdfs() {
+      for &theta in &[0.0, 0.5, 1.0, 1.4] {
+        let albedo = get_albedo(&bsdf, n, get_direction(theta));
+        assert!(
+          albedo.r <= 1.01 && albedo.g <= 1.01 && albedo.b <= 1.01,
+          "{:?} at theta {} reflects {:?}",
+          bsdf,
+          theta,
+          albedo
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn pbr_bsdf_conserves_energy() {
+    // a white dielectric is the worst case: the diffuse base and the specular coat both reflect
+    let n = PosVector::new_unit_z();
+    let coords = SurfaceCoords::new(0.0, 0.0, PosVector::new_default(), PosVector::new_default());
+    for &metallic in &[0.0, 0.5, 1.0] {
+      for &roughness in &[0.0, 0.2, 0.5, 1.0] {
+        let material = PbrMaterial::new(ColorVector::new(1.0, 1.0, 1.0), metallic, roughness);
+        let bsdf = material.get_bsdf(&coords).unwrap();
+        for &theta in &[0.0, 0.5, 1.0, 1.4, 1.55] {
+          let albedo = get_albedo(&bsdf, n, get_direction(theta));
+          assert!(
+            albedo.r <= 1.01 && albedo.g <= 1.01 && albedo.b <= 1.01,
+            "metallic {} roughness {} at theta {} reflects {:?}",
+            metallic,
+            roughness,
+            theta,
+            albedo
+          );
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn bsdf_sample_matches_pdf_and_eval() {
+    let n = PosVector::new_unit_z();
+    let wo = get_direction(0.7);
+    for bsdf in get_bsdfs() {
+      for i in 0..16 {
+        for j in 0..16 {
+          let (u1, u2) = ((i as f64 + 0.5) / 16.0, (j as f64 + 0.5) / 16.0);
+          let sample = match bsdf.sample(n, wo, (u1 + u2) / 2.0, u1, u2) {
+            Some(sample) => sample,
+            None => continue,
+          };
+          let pdf = bsdf.pdf(n, wo, sample.direction);
+          assert!((sample.pdf - pdf).abs() <= 1e-9 * pdf.max(1.0), "{} != {}", sample.pdf, pdf);
+          let expected = bsdf
+            .eval(n, wo, sample.direction)
+            .multiply_by_scalar(n.dot_product(sample.direction) / pdf);
+          assert!((sample.weight.r - expected.r).abs() <= 1e-9 * expected.r.max(1.0));
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn lobe_pdf_integrates_to_sampled_fraction() {
+    // the pdf over the hemisphere adds up to the fraction of samples that aren't rejected
+    let n = PosVector::new_unit_z();
+    let wo = get_direction(0.7);
+    let white = ColorVector::new(1.0, 1.0, 1.0);
+    let lobes = vec![
+      Lobe::Lambert { albedo: white },
+      Lobe::Ggx { f0: white, alpha: 0.3, weight: 1.0 },
+      Lobe::Ggx { f0: white, alpha: 0.8, weight: 1.0 },
+    ];
+    for lobe in lobes {
+      // midpoint rule over cos(theta) and phi, both uniform in solid angle
+      let steps = 1000;
+      let mut integral = 0.0;
+      for i in 0..steps {
+        for j in 0..steps {
+          let cos_theta = (i as f64 + 0.5) / steps as f64;
+          let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+          let phi = 2.0 * f64::consts::PI * (j as f64 + 0.5) / steps as f64;
+          let wi = PosVector::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+          integral += lobe.pdf(n, wo, wi);
+        }
+      }
+      integral *= 2.0 * f64::consts::PI / (steps * steps) as f64;
+
+      let mut accepted = 0;
+      for i in 0..GRID {
+        for j in 0..GRID {
+          let (u1, u2) = get_grid(i, j);
+          if lobe.sample(n, wo, u1, u2).is_some() {
+            accepted += 1;
+          }
+        }
+      }
+      let fraction = accepted as f64 / (GRID * GRID) as f64;
+      assert!((integral - fraction).abs() < 0.01, "{:?}: {} != {}", lobe, integral, fraction);
+    }
+  }
+}
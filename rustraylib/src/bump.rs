@@ -0,0 +1,105 @@
+use std::sync::Arc;
+
+use material::SurfaceCoords;
+use posvector::PosVector;
+use texture::Texture;
+
+// step used for the finite differences of a height texture, in uv units
+const BUMP_DELTA: f64 = 0.001;
+
+// Perturbs the shading normal of a surface.  Both kinds rely on the tangent frame
+// (coords.tangent / coords.bitangent) the shape derived from its uv parameterization.
+#[derive(Debug, Clone)]
+pub enum BumpMap {
+  // scalar height texture, displacing the surface along its normal
+  Height { texture: Arc<dyn Texture>, strength: f64 },
+  // tangent space normal map with xyz encoded in rgb as 0..1
+  Normal { texture: Arc<dyn Texture>, strength: f64 },
+}
+
+impl BumpMap {
+  pub fn new_height(texture: Arc<dyn Texture>, strength: f64) -> BumpMap {
+    BumpMap::Height { texture, strength }
+  }
+
+  pub fn new_normal(texture: Arc<dyn Texture>, strength: f64) -> BumpMap {
+    BumpMap::Normal { texture, strength }
+  }
+
+  fn shifted_coords(coords: &SurfaceCoords, du: f64, dv: f64) -> SurfaceCoords {
+    let offset = coords
+      .tangent
+      .multiply_by_scalar(du)
+      .add(coords.bitangent.multiply_by_scalar(dv));
+    let mut shifted = *coords;
+    shifted.u = coords.u + du;
+    shifted.v = coords.v + dv;
+    shifted.position = coords.position.add(offset);
+    shifted.object_position = coords.object_position.add(offset);
+    shifted
+  }
+
+  // returns the shading normal for the geometric normal n
+  pub fn perturb(&self, coords: &SurfaceCoords, n: PosVector) -> PosVector {
+    if coords.tangent.magnitude_squared() == 0.0 || coords.bitangent.magnitude_squared() == 0.0 {
+      return n;
+    }
+
+    let perturbed = match *self {
+      BumpMap::Height {
+        ref texture,
+        strength,
+      } => {
+        let h = texture.get_scalar(coords);
+        let h_u = texture.get_scalar(&BumpMap::shifted_coords(coords, BUMP_DELTA, 0.0));
+        let h_v = texture.get_scalar(&BumpMap::shifted_coords(coords, 0.0, BUMP_DELTA));
+        let dh_du = strength * (h_u - h) / BUMP_DELTA;
+        let dh_dv = strength * (h_v - h) / BUMP_DELTA;
+
+        // derivatives of the displaced surface p + h * n
+        let dpdu = coords.tangent.add(n.multiply_by_scalar(dh_du));
+        let dpdv = coords.bitangent.add(n.multiply_by_scalar(dh_dv));
+        let perturbed = dpdu.cross(dpdv);
+        // uv mappings can be left handed with respect to n
+        if coords.tangent.cross(coords.bitangent).dot_product(n) < 0.0 {
+          perturbed.multiply_by_scalar(-1.0)
+        } else {
+          perturbed
+        }
+      }
+      BumpMap::Normal {
+        ref texture,
+        strength,
+      } => {
+        let c = texture.get_value(coords);
+        let x = (c.r * 2.0 - 1.0) * strength;
+        let y = (c.g * 2.0 - 1.0) * strength;
+        let z = c.b * 2.0 - 1.0;
+
+        // orthonormal tangent frame around n, keeping the handedness of the uv mapping
+        let t = coords
+          .tangent
+          .subtract(n.multiply_by_scalar(n.dot_product(coords.tangent)))
+          .normalize();
+        let mut b = n.cross(t);
+        if b.dot_product(coords.bitangent) < 0.0 {
+          b = b.multiply_by_scalar(-1.0);
+        }
+        t.multiply_by_scalar(x)
+          .add(b.multiply_by_scalar(y))
+          .add(n.multiply_by_scalar(z))
+      }
+    };
+
+    if perturbed.magnitude_squared() == 0.0 {
+      return n;
+    }
+    let perturbed = perturbed.normalize();
+    // keep the shading normal on the same side of the surface as the geometry
+    if perturbed.dot_product(n) < 0.0 {
+      perturbed.multiply_by_scalar(-1.0)
+    } else {
+      perturbed
+    }
+  }
+}
@@ -57,7 +57,7 @@ impl ColorVector {
 
   pub fn blend(&self, other: ColorVector, weight: f64) -> ColorVector {
     let temp = self.multiply_by_scalar(1.0 - weight);
-    let temp2 = temp.add(other.multiply_by_scalar(1.0 - weight));
+    let temp2 = temp.add(other.multiply_by_scalar(weight));
     temp2
   }
 }
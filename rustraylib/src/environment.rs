@@ -0,0 +1,290 @@
+use image;
+use image::hdr::HDRDecoder;
+use std::f64;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use color::ColorVector;
+use posvector::PosVector;
+
+// A direction picked by importance sampling an environment, pdf is per solid angle.
+#[derive(Debug, Copy, Clone)]
+pub struct EnvironmentSample {
+  pub direction: PosVector,
+  pub radiance: ColorVector,
+  pub pdf: f64,
+}
+
+// Light arriving from infinitely far away, e.g. a captured studio or the sky.  It's what rays that
+// miss the scene see and it lights the scene like a light surrounding it.
+pub trait Environment: fmt::Debug + Send + Sync {
+  // direction is a unit vector pointing away from the scene
+  fn get_radiance(&self, direction: PosVector) -> ColorVector;
+  // picks a direction with u1 and u2 in [0, 1), preferring the bright parts
+  fn sample(&self, u1: f64, u2: f64) -> Option<EnvironmentSample>;
+  // pdf per solid angle of sample() picking direction
+  fn pdf(&self, direction: PosVector) -> f64;
+}
+
+// piecewise constant distribution over [0, 1) with func.len() steps
+#[derive(Debug, Clone)]
+struct Distribution1D {
+  func: Vec<f64>,
+  cdf: Vec<f64>,
+  integral: f64,
+}
+
+impl Distribution1D {
+  fn new(func: Vec<f64>) -> Distribution1D {
+    let n = func.len();
+    let mut cdf = vec![0.0; n + 1];
+    for i in 0..n {
+      cdf[i + 1] = cdf[i] + func[i] / n as f64;
+    }
+    let integral = cdf[n];
+    for (i, c) in cdf.iter_mut().enumerate().skip(1) {
+      // all black falls back to uniform
+      *c = if integral > 0.0 {
+        *c / integral
+      } else {
+        i as f64 / n as f64
+      };
+    }
+    Distribution1D {
+      func,
+      cdf,
+      integral,
+    }
+  }
+
+  // returns the position in [0, 1), its step and its pdf
+  fn sample(&self, u: f64) -> (f64, usize, f64) {
+    let n = self.func.len();
+    // last step with cdf <= u
+    let mut lo = 0;
+    let mut hi = n;
+    while hi - lo > 1 {
+      let mid = (lo + hi) / 2;
+      if self.cdf[mid] <= u {
+        lo = mid;
+      } else {
+        hi = mid;
+      }
+    }
+    let width = self.cdf[lo + 1] - self.cdf[lo];
+    let offset = if width > 0.0 {
+      (u - self.cdf[lo]) / width
+    } else {
+      0.0
+    };
+    ((lo as f64 + offset) / n as f64, lo, self.get_pdf(lo))
+  }
+
+  fn get_pdf(&self, index: usize) -> f64 {
+    if self.integral > 0.0 {
+      self.func[index] / self.integral
+    } else {
+      1.0
+    }
+  }
+}
+
+// Equirectangular (latitude-longitude) radiance map.  The top row is straight up (+z) and the
+// center column looks along +x.
+#[derive(Clone)]
+pub struct EnvironmentMap {
+  width: u32,
+  height: u32,
+  pixels: Vec<ColorVector>, // linear radiance, row major, top row first
+  rows: Distribution1D,
+  columns: Vec<Distribution1D>, // one per row
+  pub intensity: f64,
+  pub rotation: f64, // degrees around the up axis
+}
+
+// the pixel data is far too large to be useful in debug output
+impl fmt::Debug for EnvironmentMap {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("EnvironmentMap")
+      .field("width", &self.width)
+      .field("height", &self.height)
+      .field("intensity", &self.intensity)
+      .field("rotation", &self.rotation)
+      .finish()
+  }
+}
+
+impl EnvironmentMap {
+  // loads a radiance .hdr file, other image formats (png, jpeg, ...) are taken to be sRGB encoded.
+  // OpenEXR isn't supported by the image crate, those need converting to .hdr first.
+  pub fn load(file_path: &str) -> Result<EnvironmentMap, image::ImageError> {
+    let extension = Path::new(file_path)
+      .extension()
+      .and_then(|e| e.to_str())
+      .unwrap_or("")
+      .to_lowercase();
+
+    match extension.as_ref() {
+      "hdr" => {
+        let f = File::open(file_path)?;
+        let decoder = HDRDecoder::new(BufReader::new(f))?;
+        let metadata = decoder.metadata();
+        let pixels = decoder
+          .read_image_hdr()?
+          .iter()
+          .map(|p| ColorVector::new(p[0] as f64, p[1] as f64, p[2] as f64))
+          .collect();
+        Ok(EnvironmentMap::new(metadata.width, metadata.height, pixels))
+      }
+      "exr" => Err(image::ImageError::UnsupportedError(
+        "OpenEXR (convert the map to .hdr)".to_string(),
+      )),
+      _ => {
+        let img = image::open(file_path)?.to_rgb();
+        let (width, height) = img.dimensions();
+        let srgb_to_linear = |val: u8| {
+          let c = val as f64 / 255.0;
+          if c <= 0.04045 {
+            c / 12.92
+          } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+          }
+        };
+        let pixels = img
+          .pixels()
+          .map(|p| ColorVector::new(srgb_to_linear(p[0]), srgb_to_linear(p[1]), srgb_to_linear(p[2])))
+          .collect();
+        Ok(EnvironmentMap::new(width, height, pixels))
+      }
+    }
+  }
+
+  pub fn new(width: u32, height: u32, pixels: Vec<ColorVector>) -> EnvironmentMap {
+    // sampling density follows the brightness, weighted by the solid angle of each row
+    let mut columns = Vec::with_capacity(height as usize);
+    let mut row_weights = Vec::with_capacity(height as usize);
+    for y in 0..height {
+      let sin_theta = (f64::consts::PI * (y as f64 + 0.5) / height as f64).sin();
+      let row = &pixels[(y * width) as usize..((y + 1) * width) as usize];
+      let func: Vec<f64> = row
+        .iter()
+        .map(|c| (0.2126 * c.r + 0.7152 * c.g + 0.0722 * c.b).max(0.0) * sin_theta)
+        .collect();
+      let distribution = Distribution1D::new(func);
+      row_weights.push(distribution.integral);
+      columns.push(distribution);
+    }
+
+    EnvironmentMap {
+      width,
+      height,
+      pixels,
+      rows: Distribution1D::new(row_weights),
+      columns,
+      intensity: 1.0,
+      rotation: 0.0,
+    }
+  }
+
+  pub fn get_width(&self) -> u32 {
+    self.width
+  }
+
+  pub fn get_height(&self) -> u32 {
+    self.height
+  }
+
+  fn get_pixel(&self, x: usize, y: usize) -> ColorVector {
+    self.pixels[y * self.width as usize + x].multiply_by_scalar(self.intensity)
+  }
+
+  // texel coordinates in [0, 1) of a direction
+  fn direction_to_uv(&self, direction: PosVector) -> (f64, f64) {
+    let theta = direction.z.clamp(-1.0, 1.0).acos();
+    let phi = direction.y.atan2(direction.x) - self.rotation.to_radians();
+    let u = 0.5 + phi / (2.0 * f64::consts::PI);
+    (u - u.floor(), theta / f64::consts::PI)
+  }
+
+  // direction of the texel coordinates u, v in [0, 1)
+  pub fn get_direction(&self, u: f64, v: f64) -> PosVector {
+    let theta = v * f64::consts::PI;
+    let phi = (u - 0.5) * 2.0 * f64::consts::PI + self.rotation.to_radians();
+    PosVector::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos())
+  }
+}
+
+impl Environment for EnvironmentMap {
+  fn get_radiance(&self, direction: PosVector) -> ColorVector {
+    let (u, v) = self.direction_to_uv(direction);
+    let x = ((u * self.width as f64) as usize).min(self.width as usize - 1);
+    let y = ((v * self.height as f64) as usize).min(self.height as usize - 1);
+    self.get_pixel(x, y)
+  }
+
+  fn sample(&self, u1: f64, u2: f64) -> Option<EnvironmentSample> {
+    let (v, y, row_pdf) = self.rows.sample(u1);
+    let (u, x, column_pdf) = self.columns[y].sample(u2);
+    let sin_theta = (v * f64::consts::PI).sin();
+    if sin_theta <= 0.0 {
+      return None;
+    }
+    // from the density over the map to one over solid angle
+    let pdf = row_pdf * column_pdf / (2.0 * f64::consts::PI * f64::consts::PI * sin_theta);
+    if pdf <= 0.0 {
+      return None;
+    }
+    Some(EnvironmentSample {
+      direction: self.get_direction(u, v),
+      radiance: self.get_pixel(x, y),
+      pdf,
+    })
+  }
+
+  fn pdf(&self, direction: PosVector) -> f64 {
+    let (u, v) = self.direction_to_uv(direction);
+    let x = ((u * self.width as f64) as usize).min(self.width as usize - 1);
+    let y = ((v * self.height as f64) as usize).min(self.height as usize - 1);
+    let sin_theta = (v * f64::consts::PI).sin();
+    if sin_theta <= 0.0 {
+      return 0.0;
+    }
+    self.rows.get_pdf(y) * self.columns[y].get_pdf(x) / (2.0 * f64::consts::PI * f64::consts::PI * sin_theta)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn distribution_pdf_integrates_to_one() {
+    let functions = vec![
+      vec![1.0, 2.0, 3.0, 4.0],
+      vec![0.0, 5.0, 0.0, 0.5, 2.0],
+      vec![0.0, 0.0, 0.0],
+      vec![7.0],
+    ];
+    for func in functions {
+      let n = func.len();
+      let distribution = Distribution1D::new(func);
+      let integral: f64 = (0..n).map(|i| distribution.get_pdf(i) / n as f64).sum();
+      assert!((integral - 1.0).abs() < 1e-12, "integrates to {}", integral);
+    }
+  }
+
+  #[test]
+  fn distribution_sample_matches_pdf() {
+    let distribution = Distribution1D::new(vec![0.0, 5.0, 0.0, 0.5, 2.0]);
+    for i in 0..100 {
+      let u = (i as f64 + 0.5) / 100.0;
+      let (x, index, pdf) = distribution.sample(u);
+      assert!((0.0..1.0).contains(&x));
+      assert_eq!(index, (x * 5.0) as usize);
+      assert!(pdf > 0.0, "sampled step {} that has no weight", index);
+      assert_eq!(pdf, distribution.get_pdf(index));
+    }
+  }
+}
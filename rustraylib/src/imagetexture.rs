@@ -0,0 +1,173 @@
+use image;
+use std::fmt;
+
+use color::ColorVector;
+use material::SurfaceCoords;
+use texture::Texture;
+
+#[derive(Debug, Copy, Clone)]
+pub enum TextureFilter {
+  Nearest,
+  Bilinear,
+}
+
+// how texel coordinates outside of the image are brought back into it
+#[derive(Debug, Copy, Clone)]
+pub enum WrapMode {
+  Repeat,
+  Clamp,
+  Mirror,
+}
+
+#[derive(Clone)]
+pub struct ImageTexture {
+  width: u32,
+  height: u32,
+  pixels: Vec<ColorVector>, // linear color, row major, top row first
+  pub filter: TextureFilter,
+  pub wrap: WrapMode,
+  pub scale_u: f64,
+  pub scale_v: f64,
+  pub offset_u: f64,
+  pub offset_v: f64,
+}
+
+// the pixel data is far too large to be useful in debug output
+impl fmt::Debug for ImageTexture {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("ImageTexture")
+      .field("width", &self.width)
+      .field("height", &self.height)
+      .field("filter", &self.filter)
+      .field("wrap", &self.wrap)
+      .field("scale_u", &self.scale_u)
+      .field("scale_v", &self.scale_v)
+      .field("offset_u", &self.offset_u)
+      .field("offset_v", &self.offset_v)
+      .finish()
+  }
+}
+
+impl ImageTexture {
+  // loads any format the image crate understands (png, jpeg, ...).
+  // image files are sRGB encoded so they're converted to linear color on load.
+  pub fn load(file_path: &str) -> Result<ImageTexture, image::ImageError> {
+    ImageTexture::load_with_conversion(file_path, ImageTexture::srgb_to_linear)
+  }
+
+  // loads an image that holds data rather than color (normal maps, height maps) as is.
+  pub fn load_linear(file_path: &str) -> Result<ImageTexture, image::ImageError> {
+    ImageTexture::load_with_conversion(file_path, |val| val as f64 / 255.0)
+  }
+
+  fn load_with_conversion<F: Fn(u8) -> f64>(file_path: &str, convert: F) -> Result<ImageTexture, image::ImageError> {
+    let img = image::open(file_path)?.to_rgb();
+    let (width, height) = img.dimensions();
+
+    let mut pixels: Vec<ColorVector> = Vec::with_capacity((width * height) as usize);
+    for pixel in img.pixels() {
+      pixels.push(ColorVector::new(
+        convert(pixel[0]),
+        convert(pixel[1]),
+        convert(pixel[2]),
+      ));
+    }
+
+    Ok(ImageTexture {
+      width,
+      height,
+      pixels,
+      filter: TextureFilter::Bilinear,
+      wrap: WrapMode::Repeat,
+      scale_u: 1.0,
+      scale_v: 1.0,
+      offset_u: 0.0,
+      offset_v: 0.0,
+    })
+  }
+
+  pub fn get_width(&self) -> u32 {
+    self.width
+  }
+
+  pub fn get_height(&self) -> u32 {
+    self.height
+  }
+
+  fn srgb_to_linear(val: u8) -> f64 {
+    let c = val as f64 / 255.0;
+    if c <= 0.04045 {
+      c / 12.92
+    } else {
+      ((c + 0.055) / 1.055).powf(2.4)
+    }
+  }
+
+  fn wrap_coord(&self, i: i64, size: u32) -> usize {
+    let n = size as i64;
+    let wrapped = match self.wrap {
+      WrapMode::Repeat => ((i % n) + n) % n,
+      WrapMode::Clamp => {
+        if i < 0 {
+          0
+        } else if i >= n {
+          n - 1
+        } else {
+          i
+        }
+      }
+      WrapMode::Mirror => {
+        let period = 2 * n;
+        let m = ((i % period) + period) % period;
+        if m >= n {
+          period - 1 - m
+        } else {
+          m
+        }
+      }
+    };
+    wrapped as usize
+  }
+
+  fn get_texel(&self, x: i64, y: i64) -> ColorVector {
+    let tx = self.wrap_coord(x, self.width);
+    let ty = self.wrap_coord(y, self.height);
+    self.pixels[ty * self.width as usize + tx]
+  }
+
+  // (0,0) is the bottom left corner of the image and (1,1) the top right.
+  pub fn get_color(&self, u: f64, v: f64) -> ColorVector {
+    let su = u * self.scale_u + self.offset_u;
+    let sv = v * self.scale_v + self.offset_v;
+
+    // continuous texel space, texel centers are at +0.5
+    let x = su * self.width as f64 - 0.5;
+    let y = (1.0 - sv) * self.height as f64 - 0.5;
+
+    match self.filter {
+      TextureFilter::Nearest => self.get_texel(x.round() as i64, y.round() as i64),
+      TextureFilter::Bilinear => {
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let fx = x - x0;
+        let fy = y - y0;
+        let x0 = x0 as i64;
+        let y0 = y0 as i64;
+
+        let top = self
+          .get_texel(x0, y0)
+          .blend(self.get_texel(x0 + 1, y0), fx);
+        let bottom = self
+          .get_texel(x0, y0 + 1)
+          .blend(self.get_texel(x0 + 1, y0 + 1), fx);
+        top.blend(bottom, fy)
+      }
+    }
+  }
+}
+
+impl Texture for ImageTexture {
+  fn get_value(&self, coords: &SurfaceCoords) -> ColorVector {
+    self.get_color(coords.u, coords.v)
+  }
+}
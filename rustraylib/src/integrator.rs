@@ -0,0 +1,26 @@
+use std::fmt;
+use std::sync::Arc;
+
+use ambientocclusion::AmbientOcclusionIntegrator;
+use camera::Ray;
+use color::ColorVector;
+use pathtracer::PathIntegrator;
+use renderer::RenderMode;
+use tracer::RayTracer;
+use whitted::WhittedIntegrator;
+
+// A rendering algorithm: how the color seen along a camera ray is worked out.  The renderer calls it
+// once per camera ray (per wavelength in spectral mode).  The tracer it gets answers what all of them
+// ask of the scene: what a ray hits, how much light gets through to a point, which lights to sample.
+pub trait Integrator: fmt::Debug + Send + Sync {
+  fn get_radiance(&self, tracer: &RayTracer, ray: &Ray) -> ColorVector;
+}
+
+// the integrator that renders one of the built in render modes
+pub fn new_integrator(render_mode: RenderMode) -> Arc<dyn Integrator> {
+  match render_mode {
+    RenderMode::Shaded => Arc::new(WhittedIntegrator::new()),
+    RenderMode::AmbientOcclusion => Arc::new(AmbientOcclusionIntegrator::new()),
+    RenderMode::PathTraced => Arc::new(PathIntegrator::new()),
+  }
+}
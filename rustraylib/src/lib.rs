@@ -6,19 +6,50 @@ pub mod color;
 pub mod scene;
 pub mod camera;
 pub mod material;
+pub mod materiallibrary;
+pub mod texture;
+pub mod bump;
+pub mod bsdf;
+pub mod medium;
+pub mod environment;
+pub mod sky;
+pub mod sampling;
+pub mod spectral;
+pub mod imagetexture;
+pub mod noise;
 pub mod shapes;
 pub mod light;
+pub mod lightsampling;
 pub mod renderer;
 pub mod tracer;
+pub mod integrator;
+pub mod ambientocclusion;
+pub mod whitted;
+pub mod pathtracer;
 pub mod threading;
 pub mod nffparsing;
 
 pub use shapes::*;
 pub use camera::Camera;
 pub use material::*;
+pub use materiallibrary::MaterialLibrary;
+pub use texture::*;
+pub use bump::BumpMap;
+pub use bsdf::{Bsdf, BsdfSample, Lobe};
+pub use medium::HomogeneousMedium;
+pub use environment::{Environment, EnvironmentMap, EnvironmentSample};
+pub use sky::PreethamSky;
+pub use spectral::Dispersion;
+pub use imagetexture::{ImageTexture, TextureFilter, WrapMode};
+pub use noise::{NoisePattern, NoiseSpace, NoiseTexture};
 pub use tracer::RayTracer;
+pub use integrator::Integrator;
+pub use ambientocclusion::AmbientOcclusionIntegrator;
+pub use whitted::WhittedIntegrator;
+pub use pathtracer::PathIntegrator;
 pub use threading::ThreadPool;
-pub use renderer::{RenderData, PixelArray, Renderer};
+pub use lightsampling::{LightSampler, LightSampling};
+pub use renderer::{RenderData, RenderMode, PixelArray, Renderer};
 pub use scene::{Scene};
 pub use posvector::PosVector;
 pub use color::ColorVector;
@@ -1,30 +1,407 @@
+use std::f64;
 use std::fmt;
 
 use posvector::PosVector;
 use color::ColorVector;
+use sampling;
 
-pub trait Light: fmt::Debug {
-  fn get_position(&self) -> PosVector;
+// How a light reaches a shading point: the unit direction towards the light, how far away it is
+// (infinite for lights without a position) and the color arriving there.
+#[derive(Debug, Copy, Clone)]
+pub struct LightSample {
+  pub direction: PosVector,
+  pub distance: f64,
+  pub color: ColorVector,
+}
+
+// How the light from a positioned light falls off with distance.
+#[derive(Debug, Copy, Clone)]
+pub enum Attenuation {
+  None,
+  InverseSquare,
+  // 1 / (constant + linear * d + quadratic * d^2), like fixed function opengl
+  Polynomial { constant: f64, linear: f64, quadratic: f64 },
+}
+
+impl Attenuation {
+  pub fn get_factor(&self, distance: f64) -> f64 {
+    match *self {
+      Attenuation::None => 1.0,
+      Attenuation::InverseSquare => 1.0 / (distance * distance).max(1e-8),
+      Attenuation::Polynomial {
+        constant,
+        linear,
+        quadratic,
+      } => 1.0 / (constant + linear * distance + quadratic * distance * distance).max(1e-8),
+    }
+  }
+}
+
+// Light units: the color of a light is what a white diffuse surface facing it reflects, in the
+// spirit of the classic phong shading the nff scenes were made for.  a white lambert bsdf is 1 / pi,
+// so the integrators scale these lights by pi (the irradiance is pi * color * cos).  emissive shapes
+// and the environment are different, they give radiance, which is integrated over the hemisphere
+// without the pi.
+pub trait Light: fmt::Debug + Send + Sync {
+  fn illuminate(&self, point: PosVector) -> LightSample;
+  // what the light emits, its color times its intensity
   fn get_color(&self) -> ColorVector;
+
+  // lights with an extent are sampled at a point picked with u1 and u2 in [0, 1), once per shadow ray.
+  // the others always give illuminate.
+  fn sample(&self, point: PosVector, _u1: f64, _u2: f64) -> LightSample {
+    self.illuminate(point)
+  }
+  fn is_area(&self) -> bool {
+    false
+  }
+
+  // used to pick among many lights, see LightSampler.
+  // how bright the light is overall
+  fn get_power(&self) -> f64 {
+    let c = self.get_color();
+    0.2126 * c.r + 0.7152 * c.g + 0.0722 * c.b
+  }
+  // corners of a box around the light, None for lights that are infinitely far away
+  fn get_bounds(&self) -> Option<(PosVector, PosVector)> {
+    None
+  }
+  // axis and cosine of the half angle of a cone holding all the directions the light shines in
+  fn get_emission_cone(&self) -> (PosVector, f64) {
+    (PosVector::new_unit_z(), -1.0)
+  }
+}
+
+fn get_sphere_bounds(position: PosVector, radius: f64) -> Option<(PosVector, PosVector)> {
+  let extent = PosVector::new(radius, radius, radius);
+  Some((position.subtract(extent), position.add(extent)))
 }
 
+// Lights with a position don't attenuate unless asked to, the classic scenes rely on that.
 #[derive(Debug)]
 pub struct PointLight {
   position: PosVector,
   color: ColorVector,
+  pub intensity: f64,
+  pub attenuation: Attenuation,
 }
 
 impl PointLight {
   pub fn new(position: PosVector, color: ColorVector) -> PointLight {
-    PointLight { position, color }
+    PointLight {
+      position,
+      color,
+      intensity: 1.0,
+      attenuation: Attenuation::None,
+    }
   }
 }
 
 impl Light for PointLight {
-  fn get_position(&self) -> PosVector {
-    self.position
+  fn illuminate(&self, point: PosVector) -> LightSample {
+    illuminate_from(point, self.position, None, self.get_color(), self.attenuation)
+  }
+  fn get_color(&self) -> ColorVector {
+    self.color.multiply_by_scalar(self.intensity)
+  }
+  fn get_bounds(&self) -> Option<(PosVector, PosVector)> {
+    Some((self.position, self.position))
+  }
+}
+
+// Infinitely distant light like the sun, every point is lit from the same direction.
+#[derive(Debug)]
+pub struct DirectionalLight {
+  direction: PosVector, // the direction the light travels in
+  color: ColorVector,
+  pub intensity: f64,
+}
+
+impl DirectionalLight {
+  pub fn new(direction: PosVector, color: ColorVector) -> DirectionalLight {
+    DirectionalLight {
+      direction: direction.normalize(),
+      color,
+      intensity: 1.0,
+    }
+  }
+}
+
+impl Light for DirectionalLight {
+  fn illuminate(&self, _point: PosVector) -> LightSample {
+    LightSample {
+      direction: self.direction.multiply_by_scalar(-1.0),
+      distance: f64::INFINITY,
+      color: self.get_color(),
+    }
+  }
+  fn get_color(&self) -> ColorVector {
+    self.color.multiply_by_scalar(self.intensity)
+  }
+}
+
+// Point light that only shines into a cone.  Angles are in degrees from the axis, the light fades out
+// smoothly between the inner and the outer angle.
+#[derive(Debug)]
+pub struct SpotLight {
+  position: PosVector,
+  direction: PosVector,
+  color: ColorVector,
+  cos_inner: f64,
+  cos_outer: f64,
+  pub intensity: f64,
+  pub attenuation: Attenuation,
+}
+
+impl SpotLight {
+  pub fn new(
+    position: PosVector,
+    direction: PosVector,
+    color: ColorVector,
+    inner_angle: f64,
+    outer_angle: f64,
+  ) -> SpotLight {
+    let outer_angle = outer_angle.max(inner_angle);
+    SpotLight {
+      position,
+      direction: direction.normalize(),
+      color,
+      cos_inner: inner_angle.to_radians().cos(),
+      cos_outer: outer_angle.to_radians().cos(),
+      intensity: 1.0,
+      attenuation: Attenuation::None,
+    }
+  }
+
+  // 1 inside the inner cone, 0 outside the outer one and a smoothstep in between
+  fn get_falloff(&self, cos_angle: f64) -> f64 {
+    if cos_angle >= self.cos_inner {
+      return 1.0;
+    }
+    if cos_angle <= self.cos_outer {
+      return 0.0;
+    }
+    let t = (cos_angle - self.cos_outer) / (self.cos_inner - self.cos_outer);
+    t * t * (3.0 - 2.0 * t)
+  }
+}
+
+impl Light for SpotLight {
+  fn illuminate(&self, point: PosVector) -> LightSample {
+    let mut sample = illuminate_from(point, self.position, None, self.get_color(), self.attenuation);
+    let falloff = self.get_falloff(-sample.direction.dot_product(self.direction));
+    sample.color = sample.color.multiply_by_scalar(falloff);
+    sample
+  }
+  fn get_color(&self) -> ColorVector {
+    self.color.multiply_by_scalar(self.intensity)
+  }
+  fn get_bounds(&self) -> Option<(PosVector, PosVector)> {
+    Some((self.position, self.position))
+  }
+  fn get_emission_cone(&self) -> (PosVector, f64) {
+    (self.direction, self.cos_outer)
+  }
+}
+
+// Light arriving at point from light_point, which is on the surface of area lights.  One sided lights
+// pass their normal and fade out like a lambertian emitter towards grazing angles.
+fn illuminate_from(
+  point: PosVector,
+  light_point: PosVector,
+  normal: Option<PosVector>,
+  color: ColorVector,
+  attenuation: Attenuation,
+) -> LightSample {
+  let to_light = light_point.subtract(point);
+  let distance = to_light.magnitude();
+  let direction = to_light.normalize();
+  let cos_light = match normal {
+    Some(normal) => (-direction.dot_product(normal)).max(0.0),
+    None => 1.0,
+  };
+  LightSample {
+    direction,
+    distance,
+    color: color.multiply_by_scalar(cos_light * attenuation.get_factor(distance)),
+  }
+}
+
+// Rectangular panel spanned by two edges from its center, shining to the side of edge_u x edge_v.
+#[derive(Debug)]
+pub struct RectLight {
+  position: PosVector,
+  edge_u: PosVector,
+  edge_v: PosVector,
+  normal: PosVector,
+  color: ColorVector,
+  pub intensity: f64,
+  pub attenuation: Attenuation,
+}
+
+impl RectLight {
+  pub fn new(position: PosVector, edge_u: PosVector, edge_v: PosVector, color: ColorVector) -> RectLight {
+    RectLight {
+      position,
+      edge_u,
+      edge_v,
+      normal: edge_u.cross(edge_v).normalize(),
+      color,
+      intensity: 1.0,
+      attenuation: Attenuation::None,
+    }
+  }
+}
+
+impl Light for RectLight {
+  fn illuminate(&self, point: PosVector) -> LightSample {
+    illuminate_from(point, self.position, Some(self.normal), self.get_color(), self.attenuation)
+  }
+  fn get_color(&self) -> ColorVector {
+    self.color.multiply_by_scalar(self.intensity)
+  }
+  fn sample(&self, point: PosVector, u1: f64, u2: f64) -> LightSample {
+    let light_point = self
+      .position
+      .add(self.edge_u.multiply_by_scalar(u1 - 0.5))
+      .add(self.edge_v.multiply_by_scalar(u2 - 0.5));
+    illuminate_from(point, light_point, Some(self.normal), self.get_color(), self.attenuation)
+  }
+  fn is_area(&self) -> bool {
+    true
+  }
+  fn get_bounds(&self) -> Option<(PosVector, PosVector)> {
+    let (u, v) = (self.edge_u.multiply_by_scalar(0.5), self.edge_v.multiply_by_scalar(0.5));
+    let extent = PosVector::new(
+      u.x.abs() + v.x.abs(),
+      u.y.abs() + v.y.abs(),
+      u.z.abs() + v.z.abs(),
+    );
+    Some((self.position.subtract(extent), self.position.add(extent)))
+  }
+  fn get_emission_cone(&self) -> (PosVector, f64) {
+    (self.normal, 0.0)
+  }
+}
+
+// Round panel shining to the side its normal points to.
+#[derive(Debug)]
+pub struct DiskLight {
+  position: PosVector,
+  normal: PosVector,
+  radius: f64,
+  color: ColorVector,
+  pub intensity: f64,
+  pub attenuation: Attenuation,
+}
+
+impl DiskLight {
+  pub fn new(position: PosVector, normal: PosVector, radius: f64, color: ColorVector) -> DiskLight {
+    DiskLight {
+      position,
+      normal: normal.normalize(),
+      radius,
+      color,
+      intensity: 1.0,
+      attenuation: Attenuation::None,
+    }
+  }
+}
+
+impl Light for DiskLight {
+  fn illuminate(&self, point: PosVector) -> LightSample {
+    illuminate_from(point, self.position, Some(self.normal), self.get_color(), self.attenuation)
+  }
+  fn get_color(&self) -> ColorVector {
+    self.color.multiply_by_scalar(self.intensity)
+  }
+  fn sample(&self, point: PosVector, u1: f64, u2: f64) -> LightSample {
+    let r = self.radius * u1.sqrt();
+    let phi = 2.0 * f64::consts::PI * u2;
+    let (t, b) = sampling::build_basis(self.normal);
+    let light_point = self
+      .position
+      .add(t.multiply_by_scalar(r * phi.cos()))
+      .add(b.multiply_by_scalar(r * phi.sin()));
+    illuminate_from(point, light_point, Some(self.normal), self.get_color(), self.attenuation)
+  }
+  fn is_area(&self) -> bool {
+    true
+  }
+  fn get_bounds(&self) -> Option<(PosVector, PosVector)> {
+    get_sphere_bounds(self.position, self.radius)
+  }
+  fn get_emission_cone(&self) -> (PosVector, f64) {
+    (self.normal, 0.0)
+  }
+}
+
+// Glowing ball, e.g. a light bulb.  Shines evenly in all directions.
+#[derive(Debug)]
+pub struct SphereLight {
+  position: PosVector,
+  radius: f64,
+  color: ColorVector,
+  pub intensity: f64,
+  pub attenuation: Attenuation,
+}
+
+impl SphereLight {
+  pub fn new(position: PosVector, radius: f64, color: ColorVector) -> SphereLight {
+    SphereLight {
+      position,
+      radius,
+      color,
+      intensity: 1.0,
+      attenuation: Attenuation::None,
+    }
+  }
+}
+
+impl Light for SphereLight {
+  fn illuminate(&self, point: PosVector) -> LightSample {
+    illuminate_from(point, self.position, None, self.get_color(), self.attenuation)
   }
   fn get_color(&self) -> ColorVector {
-    self.color
+    self.color.multiply_by_scalar(self.intensity)
+  }
+  fn sample(&self, point: PosVector, u1: f64, u2: f64) -> LightSample {
+    // only the half facing the point can be seen from it
+    let mut offset = sampling::uniform_sample_sphere(u1, u2);
+    if offset.dot_product(point.subtract(self.position)) < 0.0 {
+      offset = offset.multiply_by_scalar(-1.0);
+    }
+    let light_point = self.position.add(offset.multiply_by_scalar(self.radius));
+    illuminate_from(point, light_point, None, self.get_color(), self.attenuation)
+  }
+  fn is_area(&self) -> bool {
+    true
+  }
+  fn get_bounds(&self) -> Option<(PosVector, PosVector)> {
+    get_sphere_bounds(self.position, self.radius)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn directional_light_is_the_same_everywhere() {
+    let mut light = DirectionalLight::new(PosVector::new(0.0, 3.0, -4.0), ColorVector::new(1.0, 0.5, 0.25));
+    light.intensity = 2.0;
+    let to_light = PosVector::new(0.0, -0.6, 0.8);
+    for point in &[
+      PosVector::new(0.0, 0.0, 0.0),
+      PosVector::new(1.0, -2.0, 3.0),
+      PosVector::new(-1e6, 5e5, 1e6),
+    ] {
+      let sample = light.illuminate(*point);
+      assert!(sample.direction.subtract(to_light).magnitude() < 1e-12, "{:?}", sample.direction);
+      assert_eq!(sample.distance, f64::INFINITY);
+      assert_eq!((sample.color.r, sample.color.g, sample.color.b), (2.0, 1.0, 0.5));
+      assert!(!light.is_area());
+      assert!(light.get_bounds().is_none());
+    }
   }
 }
@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+use std::f64;
+
+use posvector::PosVector;
+use scene::CompiledLight;
+
+// How the lights that shade a point are chosen.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightSampling {
+  // every light, every time
+  All,
+  // light_samples lights picked in proportion to their power
+  Power,
+  // light_samples lights picked with a light tree, favouring the ones that are close by and
+  // facing the point
+  Tree,
+}
+
+// a cone of directions: axis and the half angle in radians, pi being all directions
+#[derive(Debug, Copy, Clone)]
+struct Cone {
+  axis: PosVector,
+  theta: f64,
+}
+
+impl Cone {
+  fn new(axis: PosVector, cos_theta: f64) -> Cone {
+    Cone {
+      axis,
+      theta: cos_theta.clamp(-1.0, 1.0).acos(),
+    }
+  }
+
+  // the smallest cone around both
+  fn union(&self, other: &Cone) -> Cone {
+    if self.theta >= f64::consts::PI || other.theta >= f64::consts::PI {
+      return Cone {
+        axis: self.axis,
+        theta: f64::consts::PI,
+      };
+    }
+    let theta_d = self.axis.dot_product(other.axis).clamp(-1.0, 1.0).acos();
+    if (theta_d + other.theta).min(f64::consts::PI) <= self.theta {
+      return *self;
+    }
+    if (theta_d + self.theta).min(f64::consts::PI) <= other.theta {
+      return *other;
+    }
+    let theta = (self.theta + theta_d + other.theta) / 2.0;
+    if theta >= f64::consts::PI {
+      return Cone {
+        axis: self.axis,
+        theta: f64::consts::PI,
+      };
+    }
+    // turn the axis towards the other one so both fit
+    let rotation = theta - self.theta;
+    let axis = self
+      .axis
+      .multiply_by_scalar((theta_d - rotation).sin())
+      .add(other.axis.multiply_by_scalar(rotation.sin()))
+      .normalize();
+    Cone { axis, theta }
+  }
+}
+
+#[derive(Debug, Clone)]
+struct LightNode {
+  min: PosVector,
+  max: PosVector,
+  cone: Cone,
+  power: f64,
+  children: Option<(usize, usize)>,
+  light_id: u32, // for leaves
+}
+
+impl LightNode {
+  // estimate of how much light the lights below the node give point p on a surface facing n.
+  // never 0 for a light that could reach the point, so the sampling stays unbiased.
+  fn get_importance(&self, p: PosVector, n: PosVector) -> f64 {
+    let center = self.min.add(self.max).multiply_by_scalar(0.5);
+    let radius = self.max.subtract(self.min).magnitude() / 2.0;
+    let to_node = center.subtract(p);
+    let distance = to_node.magnitude();
+    // close by lights would get an unbounded weight
+    let distance2 = (distance * distance).max(radius * radius).max(1e-8);
+
+    // half the angle the bounds take up as seen from p
+    let theta_u = if distance > radius {
+      (radius / distance).asin()
+    } else {
+      f64::consts::PI
+    };
+    let direction = if distance > 0.0 {
+      to_node.divide_by_scalar(distance)
+    } else {
+      n
+    };
+
+    let mut importance = self.power / distance2;
+
+    // the lights need to shine towards p
+    if self.cone.theta < f64::consts::PI {
+      let theta = self.cone.axis.dot_product(direction.multiply_by_scalar(-1.0)).clamp(-1.0, 1.0).acos();
+      let theta_emit = (theta - self.cone.theta - theta_u).max(0.0);
+      if theta_emit >= f64::consts::FRAC_PI_2 {
+        return 0.0;
+      }
+      importance *= theta_emit.cos();
+    }
+
+    // and be above the surface
+    let theta_i = (n.dot_product(direction).clamp(-1.0, 1.0).acos() - theta_u).max(0.0);
+    if theta_i >= f64::consts::FRAC_PI_2 {
+      return 0.0;
+    }
+    importance * theta_i.cos()
+  }
+}
+
+// Picks lights for a shading point without bias: a light picked with probability pdf is weighted
+// by 1 / pdf.  Lights at infinity (directional lights) have no place in the tree, they're picked as
+// a group by power instead.
+// Emissive shapes are picked separately, in proportion to their power, so that a mesh light made of
+// many triangles costs a shadow ray per sample rather than one per triangle.
+#[derive(Debug, Clone)]
+pub struct LightSampler {
+  lights: Vec<(u32, f64)>, // id and power of every light
+  infinite_lights: Vec<(u32, f64)>,
+  nodes: Vec<LightNode>, // the tree over the other lights, the root is the first node
+  emitters: Vec<(u32, f64)>, // shape id and probability of every emissive shape
+  emitter_cdf: Vec<f64>,
+  emitter_pdfs: HashMap<u32, f64>,
+}
+
+fn get_power_sum(lights: &[(u32, f64)]) -> f64 {
+  lights.iter().map(|&(_, power)| power).sum()
+}
+
+// picks from lights in proportion to their power, uniformly if they're all black
+fn pick_by_power(lights: &[(u32, f64)], u: f64) -> Option<(u32, f64)> {
+  if lights.is_empty() {
+    return None;
+  }
+  let total = get_power_sum(lights);
+  if total <= 0.0 {
+    let i = ((u * lights.len() as f64) as usize).min(lights.len() - 1);
+    return Some((lights[i].0, 1.0 / lights.len() as f64));
+  }
+  let mut target = u * total;
+  for &(id, power) in lights {
+    if target < power {
+      return Some((id, power / total));
+    }
+    target -= power;
+  }
+  // rounding, the last light with any power
+  lights
+    .iter()
+    .rev()
+    .find(|&&(_, power)| power > 0.0)
+    .map(|&(id, power)| (id, power / total))
+}
+
+impl LightSampler {
+  // emitters are the ids and powers of the emissive shapes
+  pub fn new(lights: &HashMap<u32, Box<CompiledLight>>, emitters: &[(u32, f64)]) -> LightSampler {
+    let mut ids: Vec<u32> = lights.keys().cloned().collect();
+    // same tree no matter the hash order
+    ids.sort();
+
+    let mut sampler = LightSampler {
+      lights: Vec::new(),
+      infinite_lights: Vec::new(),
+      nodes: Vec::new(),
+      emitters: Vec::new(),
+      emitter_cdf: vec![0.0],
+      emitter_pdfs: HashMap::new(),
+    };
+    let mut leaves = Vec::new();
+    for id in ids {
+      let light = &lights[&id];
+      let power = light.get_power().max(0.0);
+      sampler.lights.push((id, power));
+      match light.get_bounds() {
+        Some((min, max)) => {
+          let (axis, cos_theta) = light.get_emission_cone();
+          leaves.push(LightNode {
+            min,
+            max,
+            cone: Cone::new(axis, cos_theta),
+            power,
+            children: None,
+            light_id: id,
+          });
+        }
+        None => sampler.infinite_lights.push((id, power)),
+      }
+    }
+    if !leaves.is_empty() {
+      sampler.build(leaves);
+    }
+
+    // all black falls back to uniform
+    let total = get_power_sum(emitters);
+    for &(id, power) in emitters {
+      let pdf = if total > 0.0 {
+        power.max(0.0) / total
+      } else {
+        1.0 / emitters.len() as f64
+      };
+      let cdf = sampler.emitter_cdf[sampler.emitter_cdf.len() - 1] + pdf;
+      sampler.emitter_cdf.push(cdf);
+      sampler.emitters.push((id, pdf));
+      sampler.emitter_pdfs.insert(id, pdf);
+    }
+    sampler
+  }
+
+  // adds the subtree over leaves to the nodes, returns the index of its root
+  fn build(&mut self, leaves: Vec<LightNode>) -> usize {
+    let index = self.nodes.len();
+    if leaves.len() == 1 {
+      self.nodes.push(leaves[0].clone());
+      return index;
+    }
+
+    let mut node = leaves[0].clone();
+    let mut centroid_min = leaves[0].min.add(leaves[0].max).multiply_by_scalar(0.5);
+    let mut centroid_max = centroid_min;
+    for leaf in &leaves[1..] {
+      node.min = PosVector::new(node.min.x.min(leaf.min.x), node.min.y.min(leaf.min.y), node.min.z.min(leaf.min.z));
+      node.max = PosVector::new(node.max.x.max(leaf.max.x), node.max.y.max(leaf.max.y), node.max.z.max(leaf.max.z));
+      node.cone = node.cone.union(&leaf.cone);
+      node.power += leaf.power;
+      let c = leaf.min.add(leaf.max).multiply_by_scalar(0.5);
+      centroid_min = PosVector::new(centroid_min.x.min(c.x), centroid_min.y.min(c.y), centroid_min.z.min(c.z));
+      centroid_max = PosVector::new(centroid_max.x.max(c.x), centroid_max.y.max(c.y), centroid_max.z.max(c.z));
+    }
+    self.nodes.push(node);
+
+    // split at the median along the longest axis of the centers
+    let extent = centroid_max.subtract(centroid_min);
+    let key = |leaf: &LightNode| {
+      let c = leaf.min.add(leaf.max);
+      if extent.x >= extent.y && extent.x >= extent.z {
+        c.x
+      } else if extent.y >= extent.z {
+        c.y
+      } else {
+        c.z
+      }
+    };
+    let mut leaves = leaves;
+    leaves.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+    let right_leaves = leaves.split_off(leaves.len() / 2);
+    let left = self.build(leaves);
+    let right = self.build(right_leaves);
+    self.nodes[index].children = Some((left, right));
+    index
+  }
+
+  pub fn get_light_count(&self) -> usize {
+    self.lights.len()
+  }
+
+  pub fn get_emitter_count(&self) -> usize {
+    self.emitters.len()
+  }
+
+  // picks an emissive shape with u in [0, 1).  returns its id, the probability it had of being
+  // picked and u stretched back to [0, 1) so it can be reused for the point on the shape.
+  pub fn sample_emitter(&self, u: f64) -> Option<(u32, f64, f64)> {
+    let n = self.emitters.len();
+    if n == 0 {
+      return None;
+    }
+    // last emitter with cdf <= u, skipping black ones
+    let mut lo = 0;
+    let mut hi = n;
+    while hi - lo > 1 {
+      let mid = (lo + hi) / 2;
+      if self.emitter_cdf[mid] <= u {
+        lo = mid;
+      } else {
+        hi = mid;
+      }
+    }
+    let (id, pdf) = self.emitters[lo];
+    if pdf <= 0.0 {
+      return None;
+    }
+    let u = ((u - self.emitter_cdf[lo]) / pdf).clamp(0.0, 1.0 - 1e-12);
+    Some((id, pdf, u))
+  }
+
+  // probability of sample_emitter picking the shape with the id
+  pub fn get_emitter_pdf(&self, id: u32) -> f64 {
+    self.emitter_pdfs.get(&id).cloned().unwrap_or(0.0)
+  }
+
+  // picks a light for point p on a surface facing n with u in [0, 1).
+  // returns the id of the light and the probability it had of being picked.
+  pub fn sample(&self, strategy: LightSampling, p: PosVector, n: PosVector, u: f64) -> Option<(u32, f64)> {
+    match strategy {
+      LightSampling::Tree => self.sample_tree(p, n, u),
+      _ => pick_by_power(&self.lights, u),
+    }
+  }
+
+  fn sample_tree(&self, p: PosVector, n: PosVector, u: f64) -> Option<(u32, f64)> {
+    // first between the lights at infinity and the tree, by power
+    let infinite_power = get_power_sum(&self.infinite_lights);
+    let tree_power = if self.nodes.is_empty() { 0.0 } else { self.nodes[0].power };
+    let infinite_probability = if self.nodes.is_empty() {
+      1.0
+    } else if self.infinite_lights.is_empty() {
+      0.0
+    } else if infinite_power + tree_power > 0.0 {
+      infinite_power / (infinite_power + tree_power)
+    } else {
+      0.5
+    };
+
+    let mut u = u;
+    if u < infinite_probability {
+      return pick_by_power(&self.infinite_lights, u / infinite_probability)
+        .map(|(id, pdf)| (id, pdf * infinite_probability));
+    }
+    u = (u - infinite_probability) / (1.0 - infinite_probability);
+
+    // then down the tree, each time reusing what's left of u
+    let mut pdf = 1.0 - infinite_probability;
+    let mut index = 0;
+    while let Some((left, right)) = self.nodes[index].children {
+      let left_importance = self.nodes[left].get_importance(p, n);
+      let right_importance = self.nodes[right].get_importance(p, n);
+      let total = left_importance + right_importance;
+      if total <= 0.0 {
+        return None;
+      }
+      let left_probability = left_importance / total;
+      if u < left_probability {
+        u /= left_probability;
+        pdf *= left_probability;
+        index = left;
+      } else {
+        u = ((u - left_probability) / (1.0 - left_probability)).min(1.0 - 1e-12);
+        pdf *= 1.0 - left_probability;
+        index = right;
+      }
+    }
+    Some((self.nodes[index].light_id, pdf))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::Arc;
+
+  use color::ColorVector;
+  use light::{DirectionalLight, Light, PointLight, RectLight, SpotLight};
+
+  fn get_sampler() -> LightSampler {
+    let white = ColorVector::new(1.0, 1.0, 1.0);
+    let lights: Vec<Box<dyn Light>> = vec![
+      Box::new(PointLight::new(PosVector::new(1.0, 2.0, 5.0), white)),
+      Box::new(PointLight::new(PosVector::new(-3.0, 0.0, 2.0), white.multiply_by_scalar(4.0))),
+      Box::new(PointLight::new(PosVector::new(10.0, 10.0, 1.0), white.multiply_by_scalar(0.5))),
+      Box::new(SpotLight::new(
+        PosVector::new(0.0, 0.0, 6.0),
+        PosVector::new(0.0, 0.0, -1.0),
+        white,
+        20.0,
+        30.0,
+      )),
+      Box::new(RectLight::new(
+        PosVector::new(2.0, -2.0, 4.0),
+        PosVector::new(1.0, 0.0, 0.0),
+        PosVector::new(0.0, 1.0, 0.0),
+        white.multiply_by_scalar(2.0),
+      )),
+      Box::new(DirectionalLight::new(PosVector::new(0.0, 1.0, -1.0), white)),
+    ];
+    let mut compiled = HashMap::new();
+    for (i, light) in lights.into_iter().enumerate() {
+      let id = i as u32 + 1;
+      compiled.insert(id, Box::new(CompiledLight::new(Arc::new(light), id)));
+    }
+    LightSampler::new(&compiled, &[(10, 1.0), (11, 0.0), (12, 3.0)])
+  }
+
+  // sums the probability of every light sample() can pick, going over u in fine steps
+  fn get_probability_sum<F: Fn(f64) -> Option<(u32, f64)>>(sample: F) -> f64 {
+    let mut probabilities = HashMap::new();
+    let steps = 100000;
+    for i in 0..steps {
+      if let Some((id, pdf)) = sample((i as f64 + 0.5) / steps as f64) {
+        let previous = probabilities.insert(id, pdf);
+        assert!(previous.is_none_or(|p| p == pdf), "light {} picked with different probabilities", id);
+      }
+    }
+    probabilities.values().sum()
+  }
+
+  #[test]
+  fn power_probabilities_sum_to_one() {
+    let sampler = get_sampler();
+    let p = PosVector::new(0.0, 0.0, 0.0);
+    let n = PosVector::new_unit_z();
+    let sum = get_probability_sum(|u| sampler.sample(LightSampling::Power, p, n, u));
+    assert!((sum - 1.0).abs() < 1e-9, "probabilities sum to {}", sum);
+  }
+
+  #[test]
+  fn tree_probabilities_sum_to_one() {
+    let sampler = get_sampler();
+    let n = PosVector::new_unit_z();
+    for p in &[PosVector::new(0.0, 0.0, 0.0), PosVector::new(3.0, -1.0, 0.5)] {
+      let sum = get_probability_sum(|u| sampler.sample(LightSampling::Tree, *p, n, u));
+      assert!((sum - 1.0).abs() < 1e-9, "probabilities sum to {}", sum);
+    }
+  }
+
+  #[test]
+  fn emitter_probabilities_sum_to_one() {
+    let sampler = get_sampler();
+    let sum = get_probability_sum(|u| sampler.sample_emitter(u).map(|(id, pdf, _)| (id, pdf)));
+    assert!((sum - 1.0).abs() < 1e-9, "probabilities sum to {}", sum);
+    assert_eq!(sampler.get_emitter_pdf(11), 0.0);
+    assert_eq!(sampler.get_emitter_pdf(12), 0.75);
+  }
+}
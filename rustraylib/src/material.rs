@@ -1,13 +1,99 @@
+use bsdf::{Bsdf, Lobe};
+use bump::BumpMap;
 use color::ColorVector;
+use medium::HomogeneousMedium;
+use posvector::PosVector;
+use spectral::Dispersion;
+use texture::{ConstantTexture, Texture};
 use std::fmt;
+use std::sync::Arc;
 
-pub trait Material: fmt::Debug {
-  fn get_color(&self, u: f64, v: f64) -> ColorVector;
+// where on a surface a material is being evaluated
+#[derive(Debug, Copy, Clone)]
+pub struct SurfaceCoords {
+  pub u: f64,
+  pub v: f64,
+  pub position: PosVector,        // world space
+  pub object_position: PosVector, // relative to the shape that was hit
+  pub tangent: PosVector,         // dp/du, zero when the shape has no uv parameterization
+  pub bitangent: PosVector,       // dp/dv
+}
+
+impl SurfaceCoords {
+  pub fn new(u: f64, v: f64, position: PosVector, object_position: PosVector) -> SurfaceCoords {
+    SurfaceCoords {
+      u,
+      v,
+      position,
+      object_position,
+      tangent: PosVector::new_default(),
+      bitangent: PosVector::new_default(),
+    }
+  }
+}
+
+pub trait Material: fmt::Debug + Send + Sync {
+  fn get_color(&self, coords: &SurfaceCoords) -> ColorVector;
   fn has_texture(&self) -> bool;
-  fn get_gloss(&self) -> f64;
-  fn get_reflection(&self) -> f64;
-  fn get_refraction(&self) -> f64;
-  fn get_transparency(&self) -> f64;
+  fn get_gloss(&self, coords: &SurfaceCoords) -> f64;
+  fn get_reflection(&self, coords: &SurfaceCoords) -> f64;
+  fn get_refraction(&self, coords: &SurfaceCoords) -> f64;
+  fn get_transparency(&self, coords: &SurfaceCoords) -> f64;
+
+  /// Kd: fraction of the surface color that is diffusely reflected.
+  fn get_diffuse(&self, _coords: &SurfaceCoords) -> f64 {
+    1.0
+  }
+
+  /// Ks: weight of the specular highlight.
+  fn get_specular(&self, coords: &SurfaceCoords) -> f64 {
+    if self.get_gloss(coords) > 0.0 {
+      1.0
+    } else {
+      0.0
+    }
+  }
+
+  /// Phong cosine power for highlights.
+  fn get_shininess(&self, coords: &SurfaceCoords) -> f64 {
+    10.0_f64.powf(self.get_gloss(coords) + 1.0)
+  }
+
+  /// Normal used for shading, e.g. after bump or normal mapping the geometric normal.
+  fn get_shading_normal(&self, _coords: &SurfaceCoords, normal: PosVector) -> PosVector {
+    normal
+  }
+
+  /// Physically based materials describe their reflectance as a bsdf, which the tracer
+  /// then uses in place of the diffuse/specular/reflection parameters above.
+  fn get_bsdf(&self, _coords: &SurfaceCoords) -> Option<Bsdf> {
+    None
+  }
+
+  /// Radiance given off by the surface itself.
+  fn get_emission(&self, _coords: &SurfaceCoords) -> ColorVector {
+    ColorVector::new(0.0, 0.0, 0.0)
+  }
+
+  /// Shapes with an emissive material are also sampled as area lights.
+  fn has_emission(&self) -> bool {
+    false
+  }
+
+  /// Index of refraction at a wavelength in nanometers, for the spectral mode.
+  fn get_refraction_at(&self, coords: &SurfaceCoords, _wavelength: f64) -> f64 {
+    self.get_refraction(coords)
+  }
+
+  /// Whether get_refraction_at depends on the wavelength.
+  fn is_dispersive(&self) -> bool {
+    false
+  }
+
+  /// What fills the inside of a closed shape with this material, seen by rays refracted into it.
+  fn get_medium(&self) -> Option<HomogeneousMedium> {
+    None
+  }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -44,91 +130,536 @@ impl SolidMaterial {
   }
 }
 
-#[derive(Debug, Copy, Clone)]
-pub struct ChessboardMaterial {
-  pub material: BaseMaterial,
-  pub color_even: ColorVector,
-  pub color_odd: ColorVector,
-  pub scale: f64,
-}
-
 impl Material for SolidMaterial {
-  fn get_color(&self, _u: f64, _v: f64) -> ColorVector {
-    self.color.clone()
+  fn get_color(&self, _coords: &SurfaceCoords) -> ColorVector {
+    self.color
   }
   fn has_texture(&self) -> bool {
     false
   }
-  fn get_gloss(&self) -> f64 {
+  fn get_gloss(&self, _coords: &SurfaceCoords) -> f64 {
     self.material.gloss
   }
-  fn get_reflection(&self) -> f64 {
+  fn get_reflection(&self, _coords: &SurfaceCoords) -> f64 {
     self.material.reflection
   }
-  fn get_refraction(&self) -> f64 {
+  fn get_refraction(&self, _coords: &SurfaceCoords) -> f64 {
     self.material.refraction
   }
-  fn get_transparency(&self) -> f64 {
+  fn get_transparency(&self, _coords: &SurfaceCoords) -> f64 {
     self.material.transparency
   }
 }
 
-impl ChessboardMaterial {
-  /// <summary>
-  /// wraps any value up in the inteval [-1,1] in a rotational manner
-  /// e.g. 1.7 -> -0.3
-  /// e.g. -1.1 -> 0.9
-  /// e.g. -2.3 -> -0.3
-  /// </summary>
-  /// <param name="t"></param>
-  /// <returns></returns>
-  // fn wrap_up(t: f64) -> f64 {
-  //   let mut x = t % 2.0;
-  //   if x < -1.0 {
-  //       x = x + 2.0;
-  //   }
-  //   if x >= 1.0 {
-  //       x = x - 2.0;
-  //   }
-  //   x
-  // }
-
-  fn wrap_up_scale(t: f64, scale: f64) -> f64 {
-    let mut x = t % scale;
-    if x < -scale / 2.0 {
-      x = x + scale;
+// an explicit medium wins over the beer-lambert absorption of a transparent material
+fn get_interior_medium(
+  medium: Option<HomogeneousMedium>,
+  absorption_color: ColorVector,
+  absorption_density: f64,
+) -> Option<HomogeneousMedium> {
+  if medium.is_some() {
+    medium
+  } else if absorption_density > 0.0 {
+    Some(HomogeneousMedium::new_tinted(absorption_color, absorption_density))
+  } else {
+    None
+  }
+}
+
+// Material as described by the NFF "f" command:
+// "f" red green blue Kd Ks Shine T index_of_refraction
+#[derive(Debug, Copy, Clone)]
+pub struct PhongMaterial {
+  pub color: ColorVector,
+  pub kd: f64,    // diffuse component
+  pub ks: f64,    // specular component, also the weight of the reflected ray
+  pub shine: f64, // phong cosine power for highlights
+  pub t: f64,     // transmittance (fraction of contribution of the transmitting ray)
+  pub ior: f64,   // index of refraction
+  pub absorption_color: ColorVector, // tint of transparent objects, see HomogeneousMedium::new_tinted
+  pub absorption_density: f64,       // 0.0 for no absorption, higher for darker tints
+  pub medium: Option<HomogeneousMedium>,
+  pub dispersion: Option<Dispersion>, // replaces ior when rendering spectrally
+}
+
+impl PhongMaterial {
+  pub fn new(color: ColorVector, kd: f64, ks: f64, shine: f64, t: f64, ior: f64) -> PhongMaterial {
+    PhongMaterial {
+      color,
+      kd,
+      ks,
+      shine,
+      t,
+      // some nff files use 0 for objects that don't transmit
+      ior: if ior > 0.0 { ior } else { 1.0 },
+      absorption_color: ColorVector::new(1.0, 1.0, 1.0),
+      absorption_density: 0.0,
+      medium: None,
+      dispersion: None,
     }
-    if x >= scale / 2.0 {
-      x = x - scale;
+  }
+}
+
+impl Material for PhongMaterial {
+  fn get_color(&self, _coords: &SurfaceCoords) -> ColorVector {
+    self.color
+  }
+  fn has_texture(&self) -> bool {
+    false
+  }
+  fn get_gloss(&self, _coords: &SurfaceCoords) -> f64 {
+    self.shine.max(1.0).log10() - 1.0
+  }
+  fn get_reflection(&self, _coords: &SurfaceCoords) -> f64 {
+    self.ks
+  }
+  fn get_refraction(&self, _coords: &SurfaceCoords) -> f64 {
+    self.ior
+  }
+  fn get_transparency(&self, _coords: &SurfaceCoords) -> f64 {
+    self.t
+  }
+  fn get_diffuse(&self, _coords: &SurfaceCoords) -> f64 {
+    self.kd
+  }
+  fn get_specular(&self, _coords: &SurfaceCoords) -> f64 {
+    self.ks
+  }
+  fn get_shininess(&self, _coords: &SurfaceCoords) -> f64 {
+    self.shine
+  }
+  fn get_refraction_at(&self, _coords: &SurfaceCoords, wavelength: f64) -> f64 {
+    match self.dispersion {
+      Some(ref dispersion) => dispersion.get_ior(wavelength),
+      None => self.ior,
+    }
+  }
+  fn is_dispersive(&self) -> bool {
+    self.dispersion.is_some()
+  }
+  fn get_medium(&self) -> Option<HomogeneousMedium> {
+    get_interior_medium(self.medium, self.absorption_color, self.absorption_density)
+  }
+}
+
+// Material whose parameters are each looked up from their own texture.
+#[derive(Debug, Clone)]
+pub struct TexturedMaterial {
+  pub color: Arc<dyn Texture>,
+  pub gloss: Arc<dyn Texture>,
+  pub reflection: Arc<dyn Texture>,
+  pub refraction: Arc<dyn Texture>,
+  pub transparency: Arc<dyn Texture>,
+  pub bump: Option<BumpMap>,
+  pub emission: Option<Arc<dyn Texture>>,
+  pub absorption_color: ColorVector,
+  pub absorption_density: f64,
+  pub medium: Option<HomogeneousMedium>,
+}
+
+impl TexturedMaterial {
+  // textured color, everything else constant
+  pub fn new(material: BaseMaterial, color: Arc<dyn Texture>) -> TexturedMaterial {
+    TexturedMaterial {
+      color,
+      gloss: Arc::new(ConstantTexture::new_scalar(material.gloss)),
+      reflection: Arc::new(ConstantTexture::new_scalar(material.reflection)),
+      refraction: Arc::new(ConstantTexture::new_scalar(material.refraction)),
+      transparency: Arc::new(ConstantTexture::new_scalar(material.transparency)),
+      bump: None,
+      emission: None,
+      absorption_color: ColorVector::new(1.0, 1.0, 1.0),
+      absorption_density: 0.0,
+      medium: None,
     }
-    x
   }
 }
 
-impl Material for ChessboardMaterial {
-  fn get_color(&self, u: f64, v: f64) -> ColorVector {
-    let t = ChessboardMaterial::wrap_up_scale(u, self.scale)
-      * ChessboardMaterial::wrap_up_scale(v, self.scale);
-    if t < 0.0 {
-      self.color_even.clone()
+impl Material for TexturedMaterial {
+  fn get_color(&self, coords: &SurfaceCoords) -> ColorVector {
+    self.color.get_value(coords)
+  }
+  fn has_texture(&self) -> bool {
+    true
+  }
+  fn get_gloss(&self, coords: &SurfaceCoords) -> f64 {
+    self.gloss.get_scalar(coords)
+  }
+  fn get_reflection(&self, coords: &SurfaceCoords) -> f64 {
+    self.reflection.get_scalar(coords)
+  }
+  fn get_refraction(&self, coords: &SurfaceCoords) -> f64 {
+    self.refraction.get_scalar(coords)
+  }
+  fn get_transparency(&self, coords: &SurfaceCoords) -> f64 {
+    self.transparency.get_scalar(coords)
+  }
+  fn get_shading_normal(&self, coords: &SurfaceCoords, normal: PosVector) -> PosVector {
+    match self.bump {
+      Some(ref bump) => bump.perturb(coords, normal),
+      None => normal,
+    }
+  }
+  fn get_emission(&self, coords: &SurfaceCoords) -> ColorVector {
+    match self.emission {
+      Some(ref emission) => emission.get_value(coords),
+      None => ColorVector::new(0.0, 0.0, 0.0),
+    }
+  }
+  fn has_emission(&self) -> bool {
+    self.emission.is_some()
+  }
+  fn get_medium(&self) -> Option<HomogeneousMedium> {
+    get_interior_medium(self.medium, self.absorption_color, self.absorption_density)
+  }
+}
+
+// Metal/roughness material as used by glTF and most PBR pipelines.
+#[derive(Debug, Clone)]
+pub struct PbrMaterial {
+  pub base_color: Arc<dyn Texture>,
+  pub metallic: Arc<dyn Texture>,
+  pub roughness: Arc<dyn Texture>, // perceptual roughness, squared to get the ggx alpha
+  pub specular: f64,           // reflectance of dielectrics, 0.5 is an f0 of 4%
+  pub bump: Option<BumpMap>,
+  pub emission: Option<Arc<dyn Texture>>,
+}
+
+impl PbrMaterial {
+  pub fn new(base_color: ColorVector, metallic: f64, roughness: f64) -> PbrMaterial {
+    PbrMaterial {
+      base_color: Arc::new(ConstantTexture::new(base_color)),
+      metallic: Arc::new(ConstantTexture::new_scalar(metallic)),
+      roughness: Arc::new(ConstantTexture::new_scalar(roughness)),
+      specular: 0.5,
+      bump: None,
+      emission: None,
+    }
+  }
+}
+
+impl Material for PbrMaterial {
+  fn get_color(&self, coords: &SurfaceCoords) -> ColorVector {
+    self.base_color.get_value(coords)
+  }
+  fn has_texture(&self) -> bool {
+    true
+  }
+  fn get_gloss(&self, coords: &SurfaceCoords) -> f64 {
+    1.0 - self.roughness.get_scalar(coords)
+  }
+  // reflections come from the specular lobe of the bsdf
+  fn get_reflection(&self, _coords: &SurfaceCoords) -> f64 {
+    0.0
+  }
+  fn get_refraction(&self, _coords: &SurfaceCoords) -> f64 {
+    1.0
+  }
+  fn get_transparency(&self, _coords: &SurfaceCoords) -> f64 {
+    0.0
+  }
+  fn get_shading_normal(&self, coords: &SurfaceCoords, normal: PosVector) -> PosVector {
+    match self.bump {
+      Some(ref bump) => bump.perturb(coords, normal),
+      None => normal,
+    }
+  }
+  fn get_bsdf(&self, coords: &SurfaceCoords) -> Option<Bsdf> {
+    let base_color = self.base_color.get_value(coords);
+    let metallic = self.metallic.get_scalar(coords).clamp(0.0, 1.0);
+    let roughness = self.roughness.get_scalar(coords).clamp(0.0, 1.0);
+
+    let dielectric_f0 = 0.08 * self.specular;
+    let f0 = ColorVector::new(dielectric_f0, dielectric_f0, dielectric_f0).blend(base_color, metallic);
+
+    Some(Bsdf::new(vec![
+      // the diffuse base only gets the light the specular lobe doesn't reflect
+      Lobe::Coated {
+        albedo: base_color.multiply_by_scalar(1.0 - metallic),
+        f0,
+      },
+      Lobe::Ggx {
+        f0,
+        alpha: roughness * roughness,
+        weight: 1.0,
+      },
+    ]))
+  }
+  fn get_emission(&self, coords: &SurfaceCoords) -> ColorVector {
+    match self.emission {
+      Some(ref emission) => emission.get_value(coords),
+      None => ColorVector::new(0.0, 0.0, 0.0),
+    }
+  }
+  fn has_emission(&self) -> bool {
+    self.emission.is_some()
+  }
+}
+
+// A surface that only gives off light, e.g. a light panel or a neon tube.
+#[derive(Debug, Copy, Clone)]
+pub struct EmissiveMaterial {
+  pub color: ColorVector,
+  pub intensity: f64,
+}
+
+impl EmissiveMaterial {
+  pub fn new(color: ColorVector, intensity: f64) -> EmissiveMaterial {
+    EmissiveMaterial { color, intensity }
+  }
+}
+
+impl Material for EmissiveMaterial {
+  // the surface itself doesn't reflect anything
+  fn get_color(&self, _coords: &SurfaceCoords) -> ColorVector {
+    ColorVector::new(0.0, 0.0, 0.0)
+  }
+  fn has_texture(&self) -> bool {
+    false
+  }
+  fn get_gloss(&self, _coords: &SurfaceCoords) -> f64 {
+    0.0
+  }
+  fn get_reflection(&self, _coords: &SurfaceCoords) -> f64 {
+    0.0
+  }
+  fn get_refraction(&self, _coords: &SurfaceCoords) -> f64 {
+    1.0
+  }
+  fn get_transparency(&self, _coords: &SurfaceCoords) -> f64 {
+    0.0
+  }
+  fn get_diffuse(&self, _coords: &SurfaceCoords) -> f64 {
+    0.0
+  }
+  fn get_emission(&self, _coords: &SurfaceCoords) -> ColorVector {
+    self.color.multiply_by_scalar(self.intensity)
+  }
+  fn has_emission(&self) -> bool {
+    true
+  }
+}
+
+// bsdf of a material that is combined with others.  materials without one get the stand in of
+// Bsdf::new_phong, so their highlight survives as a glossy lobe.  their mirror reflection and
+// transparency still come from get_reflection / get_transparency.
+fn get_bsdf_or_phong(material: &dyn Material, coords: &SurfaceCoords) -> Bsdf {
+  match material.get_bsdf(coords) {
+    Some(bsdf) => bsdf,
+    None => Bsdf::new_phong(
+      material.get_color(coords),
+      material.get_diffuse(coords),
+      material.get_specular(coords),
+      material.get_shininess(coords),
+    ),
+  }
+}
+
+fn lerp(a: f64, b: f64, weight: f64) -> f64 {
+  a * (1.0 - weight) + b * weight
+}
+
+// Blends two materials by a mask, 0.0 is all a and 1.0 is all b.  Every parameter is blended
+// the same way so the result stays consistent, e.g. for decals or dirt over another surface.
+#[derive(Debug, Clone)]
+pub struct MixMaterial {
+  pub a: Arc<dyn Material>,
+  pub b: Arc<dyn Material>,
+  pub mask: Arc<dyn Texture>,
+}
+
+impl MixMaterial {
+  pub fn new(a: Arc<dyn Material>, b: Arc<dyn Material>, weight: f64) -> MixMaterial {
+    MixMaterial::new_masked(a, b, Arc::new(ConstantTexture::new_scalar(weight)))
+  }
+
+  pub fn new_masked(a: Arc<dyn Material>, b: Arc<dyn Material>, mask: Arc<dyn Texture>) -> MixMaterial {
+    MixMaterial { a, b, mask }
+  }
+
+  fn get_weight(&self, coords: &SurfaceCoords) -> f64 {
+    self.mask.get_scalar(coords).clamp(0.0, 1.0)
+  }
+}
+
+impl Material for MixMaterial {
+  fn get_color(&self, coords: &SurfaceCoords) -> ColorVector {
+    self
+      .a
+      .get_color(coords)
+      .blend(self.b.get_color(coords), self.get_weight(coords))
+  }
+  // the mask needs uv coordinates
+  fn has_texture(&self) -> bool {
+    true
+  }
+  fn get_gloss(&self, coords: &SurfaceCoords) -> f64 {
+    lerp(self.a.get_gloss(coords), self.b.get_gloss(coords), self.get_weight(coords))
+  }
+  fn get_reflection(&self, coords: &SurfaceCoords) -> f64 {
+    lerp(self.a.get_reflection(coords), self.b.get_reflection(coords), self.get_weight(coords))
+  }
+  fn get_refraction(&self, coords: &SurfaceCoords) -> f64 {
+    lerp(self.a.get_refraction(coords), self.b.get_refraction(coords), self.get_weight(coords))
+  }
+  fn get_refraction_at(&self, coords: &SurfaceCoords, wavelength: f64) -> f64 {
+    lerp(
+      self.a.get_refraction_at(coords, wavelength),
+      self.b.get_refraction_at(coords, wavelength),
+      self.get_weight(coords),
+    )
+  }
+  fn is_dispersive(&self) -> bool {
+    self.a.is_dispersive() || self.b.is_dispersive()
+  }
+  fn get_transparency(&self, coords: &SurfaceCoords) -> f64 {
+    lerp(self.a.get_transparency(coords), self.b.get_transparency(coords), self.get_weight(coords))
+  }
+  fn get_diffuse(&self, coords: &SurfaceCoords) -> f64 {
+    lerp(self.a.get_diffuse(coords), self.b.get_diffuse(coords), self.get_weight(coords))
+  }
+  fn get_specular(&self, coords: &SurfaceCoords) -> f64 {
+    lerp(self.a.get_specular(coords), self.b.get_specular(coords), self.get_weight(coords))
+  }
+  fn get_shininess(&self, coords: &SurfaceCoords) -> f64 {
+    lerp(self.a.get_shininess(coords), self.b.get_shininess(coords), self.get_weight(coords))
+  }
+  fn get_shading_normal(&self, coords: &SurfaceCoords, normal: PosVector) -> PosVector {
+    let weight = self.get_weight(coords);
+    let blended = self
+      .a
+      .get_shading_normal(coords, normal)
+      .multiply_by_scalar(1.0 - weight)
+      .add(self.b.get_shading_normal(coords, normal).multiply_by_scalar(weight));
+    if blended.magnitude_squared() > 0.0 {
+      blended.normalize()
     } else {
-      self.color_odd.clone()
+      normal
+    }
+  }
+  // only physically based if one of the two is, see get_bsdf_or_phong for the other one
+  fn get_bsdf(&self, coords: &SurfaceCoords) -> Option<Bsdf> {
+    let bsdf_a = self.a.get_bsdf(coords);
+    let bsdf_b = self.b.get_bsdf(coords);
+    if bsdf_a.is_none() && bsdf_b.is_none() {
+      return None;
     }
+    let bsdf_a = bsdf_a.unwrap_or_else(|| get_bsdf_or_phong(&*self.a, coords));
+    let bsdf_b = bsdf_b.unwrap_or_else(|| get_bsdf_or_phong(&*self.b, coords));
+
+    let weight = self.get_weight(coords);
+    let mut bsdf = Bsdf::new(Vec::new());
+    bsdf.add_scaled(&bsdf_a, 1.0 - weight);
+    bsdf.add_scaled(&bsdf_b, weight);
+    Some(bsdf)
+  }
+  fn get_emission(&self, coords: &SurfaceCoords) -> ColorVector {
+    self
+      .a
+      .get_emission(coords)
+      .blend(self.b.get_emission(coords), self.get_weight(coords))
   }
+  fn has_emission(&self) -> bool {
+    self.a.has_emission() || self.b.has_emission()
+  }
+  // a volume can't be blended, a takes precedence
+  fn get_medium(&self) -> Option<HomogeneousMedium> {
+    self.a.get_medium().or_else(|| self.b.get_medium())
+  }
+}
 
+// A clear dielectric coat over a base material, e.g. car paint or varnished wood.
+// The coat adds a glossy reflection on top and lets through what it doesn't reflect.
+#[derive(Debug, Clone)]
+pub struct LayeredMaterial {
+  pub base: Arc<dyn Material>,
+  pub coat: Arc<dyn Texture>, // how much of the surface is coated, 0..1
+  pub coat_roughness: f64,
+  pub coat_ior: f64,
+}
+
+impl LayeredMaterial {
+  pub fn new(base: Arc<dyn Material>, coat: f64, coat_roughness: f64) -> LayeredMaterial {
+    LayeredMaterial {
+      base,
+      coat: Arc::new(ConstantTexture::new_scalar(coat)),
+      coat_roughness,
+      coat_ior: 1.5,
+    }
+  }
+
+  fn get_coverage(&self, coords: &SurfaceCoords) -> f64 {
+    self.coat.get_scalar(coords).clamp(0.0, 1.0)
+  }
+
+  // reflectance of the coat at normal incidence
+  fn get_coat_f0(&self) -> f64 {
+    ((self.coat_ior - 1.0) / (self.coat_ior + 1.0)).powi(2)
+  }
+
+  // fraction of the light the coat keeps from the base, ignoring the fresnel falloff
+  fn get_coat_reflectance(&self, coords: &SurfaceCoords) -> f64 {
+    self.get_coat_f0() * self.get_coverage(coords)
+  }
+}
+
+impl Material for LayeredMaterial {
+  // light reaching the base has passed through the coat
+  fn get_color(&self, coords: &SurfaceCoords) -> ColorVector {
+    self
+      .base
+      .get_color(coords)
+      .multiply_by_scalar(1.0 - self.get_coat_reflectance(coords))
+  }
   fn has_texture(&self) -> bool {
     true
   }
-  fn get_gloss(&self) -> f64 {
-    self.material.gloss
+  fn get_gloss(&self, coords: &SurfaceCoords) -> f64 {
+    self.base.get_gloss(coords).max(1.0 - self.coat_roughness)
   }
-  fn get_reflection(&self) -> f64 {
-    self.material.reflection
+  fn get_reflection(&self, coords: &SurfaceCoords) -> f64 {
+    self.base.get_reflection(coords)
   }
-  fn get_refraction(&self) -> f64 {
-    self.material.refraction
+  fn get_refraction(&self, coords: &SurfaceCoords) -> f64 {
+    self.base.get_refraction(coords)
   }
-  fn get_transparency(&self) -> f64 {
-    self.material.transparency
+  fn get_refraction_at(&self, coords: &SurfaceCoords, wavelength: f64) -> f64 {
+    self.base.get_refraction_at(coords, wavelength)
+  }
+  fn is_dispersive(&self) -> bool {
+    self.base.is_dispersive()
+  }
+  fn get_transparency(&self, coords: &SurfaceCoords) -> f64 {
+    self.base.get_transparency(coords)
+  }
+  fn get_diffuse(&self, coords: &SurfaceCoords) -> f64 {
+    self.base.get_diffuse(coords)
+  }
+  fn get_shading_normal(&self, coords: &SurfaceCoords, normal: PosVector) -> PosVector {
+    self.base.get_shading_normal(coords, normal)
+  }
+  fn get_bsdf(&self, coords: &SurfaceCoords) -> Option<Bsdf> {
+    let f0 = self.get_coat_f0();
+    let roughness = self.coat_roughness.clamp(0.0, 1.0);
+
+    let mut bsdf = Bsdf::new(vec![Lobe::Ggx {
+      f0: ColorVector::new(f0, f0, f0),
+      alpha: roughness * roughness,
+      weight: self.get_coverage(coords),
+    }]);
+    bsdf.add_scaled(
+      &get_bsdf_or_phong(&*self.base, coords),
+      1.0 - self.get_coat_reflectance(coords),
+    );
+    Some(bsdf)
+  }
+  fn get_emission(&self, coords: &SurfaceCoords) -> ColorVector {
+    self.base.get_emission(coords)
+  }
+  fn has_emission(&self) -> bool {
+    self.base.has_emission()
+  }
+  fn get_medium(&self) -> Option<HomogeneousMedium> {
+    self.base.get_medium()
   }
 }
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::sync::Arc;
+
+use color::ColorVector;
+use material::*;
+use noise::NoiseTexture;
+use spectral::Dispersion;
+use texture::{CheckerTexture, Texture};
+
+// Materials registered by name so that scene files and scene builders can share them.
+//
+// Library files have one material per line, the first word being its type:
+//   phong    name r g b Kd Ks Shine T index_of_refraction   (same as the NFF "f" command)
+//   solid    name r g b gloss reflection refraction transparency
+//   pbr      name r g b metallic roughness
+//   emissive name r g b intensity
+//   glass    name r g b density index_of_refraction [abbe_number]
+//            tinted (see HomogeneousMedium::new_tinted) and optionally dispersive (see Dispersion::from_abbe)
+//   checker  name r g b r g b scale
+//   marble   name r g b r g b scale                       (also wood and granite)
+//   mix      name material_a material_b weight           (of two materials defined earlier)
+//   layer    name base_material coat coat_roughness      (clear coat over a material defined earlier)
+// Everything after a # is a comment, on its own line or after a material.  Defining a name again
+// replaces the earlier material.
+#[derive(Debug, Clone, Default)]
+pub struct MaterialLibrary {
+  materials: HashMap<String, Arc<dyn Material>>,
+}
+
+fn as_f64(s: &str) -> Option<f64> {
+  s.parse::<f64>().ok()
+}
+
+fn as_color(vec: &[&str], i: usize) -> Option<ColorVector> {
+  Some(ColorVector::new(as_f64(vec[i])?, as_f64(vec[i + 1])?, as_f64(vec[i + 2])?))
+}
+
+impl MaterialLibrary {
+  pub fn new() -> MaterialLibrary {
+    MaterialLibrary {
+      materials: HashMap::new(),
+    }
+  }
+
+  // a library with the built-in presets
+  pub fn new_with_presets() -> MaterialLibrary {
+    let mut library = MaterialLibrary::new();
+    let white = ColorVector::new(1.0, 1.0, 1.0);
+
+    let matte = |r, g, b| Arc::new(PhongMaterial::new(ColorVector::new(r, g, b), 1.0, 0.0, 0.0, 0.0, 1.0));
+    library.insert("white", matte(0.8, 0.8, 0.8));
+    library.insert("black", matte(0.02, 0.02, 0.02));
+
+    let plastic = |r, g, b| Arc::new(PhongMaterial::new(ColorVector::new(r, g, b), 0.8, 0.2, 60.0, 0.0, 1.0));
+    library.insert("red_plastic", plastic(0.8, 0.1, 0.1));
+    library.insert("green_plastic", plastic(0.1, 0.8, 0.1));
+    library.insert("blue_plastic", plastic(0.1, 0.1, 0.8));
+
+    library.insert(
+      "mirror",
+      Arc::new(PhongMaterial::new(ColorVector::new(0.0, 0.0, 0.0), 0.0, 1.0, 1000.0, 0.0, 1.0)),
+    );
+
+    // the dispersion only shows in spectral renders
+    let dielectric = |ior, dispersion| {
+      let mut material = PhongMaterial::new(white, 0.0, 0.5, 200.0, 1.0, ior);
+      material.dispersion = Some(dispersion);
+      Arc::new(material)
+    };
+    library.insert("glass", dielectric(1.5168, Dispersion::new_bk7()));
+    library.insert("flint_glass", dielectric(1.7847, Dispersion::from_abbe(1.7847, 25.7)));
+    library.insert("water", dielectric(1.333, Dispersion::from_abbe(1.333, 55.7)));
+    library.insert("diamond", dielectric(2.417, Dispersion::new_diamond()));
+    library.insert(
+      "green_glass",
+      Arc::new(MaterialLibrary::new_tinted_glass(ColorVector::new(0.55, 0.85, 0.6), 1.0, 1.5)),
+    );
+    library.insert(
+      "amber_glass",
+      Arc::new(MaterialLibrary::new_tinted_glass(ColorVector::new(0.9, 0.55, 0.15), 1.0, 1.5)),
+    );
+
+    // measured f0 of the metals
+    let metal = |r, g, b, roughness| Arc::new(PbrMaterial::new(ColorVector::new(r, g, b), 1.0, roughness));
+    library.insert("chrome", metal(0.55, 0.56, 0.55, 0.05));
+    library.insert("silver", metal(0.97, 0.96, 0.91, 0.15));
+    library.insert("aluminium", metal(0.91, 0.92, 0.92, 0.3));
+    library.insert("gold", metal(1.0, 0.77, 0.34, 0.2));
+    library.insert("copper", metal(0.95, 0.64, 0.54, 0.25));
+
+    let pattern = |kind, color_a, color_b, scale| MaterialLibrary::new_patterned(kind, color_a, color_b, scale).unwrap();
+    library.insert(
+      "checker",
+      pattern("checker", ColorVector::new(0.8, 0.8, 0.8), ColorVector::new(0.0, 0.0, 0.0), 15.0),
+    );
+    library.insert(
+      "marble",
+      pattern("marble", ColorVector::new(0.25, 0.25, 0.3), ColorVector::new(0.9, 0.9, 0.88), 1.0),
+    );
+    library.insert(
+      "wood",
+      pattern("wood", ColorVector::new(0.35, 0.18, 0.07), ColorVector::new(0.65, 0.42, 0.2), 1.0),
+    );
+    library.insert(
+      "granite",
+      pattern("granite", ColorVector::new(0.1, 0.1, 0.1), ColorVector::new(0.7, 0.68, 0.65), 1.0),
+    );
+
+    library.insert("light", Arc::new(EmissiveMaterial::new(white, 1.0)));
+
+    library
+  }
+
+  pub fn insert(&mut self, name: &str, material: Arc<dyn Material>) {
+    self.materials.insert(name.to_string(), material);
+  }
+
+  pub fn get(&self, name: &str) -> Option<Arc<dyn Material>> {
+    self.materials.get(name).cloned()
+  }
+
+  pub fn contains(&self, name: &str) -> bool {
+    self.materials.contains_key(name)
+  }
+
+  pub fn get_names(&self) -> Vec<String> {
+    let mut names: Vec<String> = self.materials.keys().cloned().collect();
+    names.sort();
+    names
+  }
+
+  // adds the materials defined in a library file, see the format above.  lines that can't be
+  // parsed are InvalidData errors, the materials before them have been added by then.
+  pub fn load(&mut self, file_path: &str) -> io::Result<()> {
+    let f = File::open(file_path)?;
+    let file = BufReader::new(&f);
+    for (num, line) in file.lines().enumerate() {
+      let l = line?;
+      let definition = l.split('#').next().unwrap_or("");
+      let vec: Vec<&str> = definition.split_whitespace().collect();
+      if vec.is_empty() {
+        continue;
+      }
+      let material = if vec.len() < 2 {
+        None
+      } else {
+        self.parse_material(&vec)
+      };
+      match material {
+        Some(material) => self.insert(vec[1], material),
+        None => {
+          return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}:{}: can't parse material '{}'", file_path, num + 1, l),
+          ))
+        }
+      }
+    }
+    Ok(())
+  }
+
+  fn parse_material(&self, vec: &[&str]) -> Option<Arc<dyn Material>> {
+    let kind = vec[0];
+    let param_count = match kind {
+      "phong" => 8,
+      "solid" => 7,
+      "pbr" => 5,
+      "emissive" => 4,
+      "glass" => 5,
+      "checker" | "marble" | "wood" | "granite" => 7,
+      "mix" | "layer" => 3,
+      _ => return None,
+    };
+    if vec.len() < 2 + param_count {
+      return None;
+    }
+
+    let material: Arc<dyn Material> = match kind {
+      "phong" => Arc::new(PhongMaterial::new(
+        as_color(vec, 2)?,
+        as_f64(vec[5])?,
+        as_f64(vec[6])?,
+        as_f64(vec[7])?,
+        as_f64(vec[8])?,
+        as_f64(vec[9])?,
+      )),
+      "solid" => Arc::new(SolidMaterial::new(
+        as_f64(vec[5])?,
+        as_f64(vec[6])?,
+        as_f64(vec[7])?,
+        as_f64(vec[8])?,
+        as_color(vec, 2)?,
+      )),
+      "pbr" => Arc::new(PbrMaterial::new(as_color(vec, 2)?, as_f64(vec[5])?, as_f64(vec[6])?)),
+      "emissive" => Arc::new(EmissiveMaterial::new(as_color(vec, 2)?, as_f64(vec[5])?)),
+      "glass" => {
+        let mut glass = MaterialLibrary::new_tinted_glass(as_color(vec, 2)?, as_f64(vec[5])?, as_f64(vec[6])?);
+        if let Some(abbe) = vec.get(7) {
+          glass.dispersion = Some(Dispersion::from_abbe(glass.ior, as_f64(abbe)?));
+        }
+        Arc::new(glass)
+      }
+      "mix" => Arc::new(MixMaterial::new(self.get(vec[2])?, self.get(vec[3])?, as_f64(vec[4])?)),
+      "layer" => Arc::new(LayeredMaterial::new(self.get(vec[2])?, as_f64(vec[3])?, as_f64(vec[4])?)),
+      _ => return MaterialLibrary::new_patterned(kind, as_color(vec, 2)?, as_color(vec, 5)?, as_f64(vec[8])?),
+    };
+    Some(material)
+  }
+
+  fn new_tinted_glass(color: ColorVector, density: f64, ior: f64) -> PhongMaterial {
+    let mut glass = PhongMaterial::new(ColorVector::new(1.0, 1.0, 1.0), 0.0, 0.5, 200.0, 1.0, ior);
+    glass.absorption_color = color;
+    glass.absorption_density = density;
+    glass
+  }
+
+  // polished surface with a checker or noise pattern
+  fn new_patterned(kind: &str, color_a: ColorVector, color_b: ColorVector, scale: f64) -> Option<Arc<dyn Material>> {
+    let texture: Arc<dyn Texture> = match kind {
+      "checker" => Arc::new(CheckerTexture::new(color_a, color_b, scale)),
+      "marble" => Arc::new(NoiseTexture::new_marble(color_a, color_b, scale)),
+      "wood" => Arc::new(NoiseTexture::new_wood(color_a, color_b, scale)),
+      "granite" => Arc::new(NoiseTexture::new_granite(color_a, color_b, scale)),
+      _ => return None,
+    };
+    let polished = BaseMaterial {
+      gloss: 1.0,
+      reflection: 0.2,
+      refraction: 0.0,
+      transparency: 0.0,
+    };
+    Some(Arc::new(TexturedMaterial::new(polished, texture)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+  use std::fs;
+
+  // writes the library to a temporary file and loads it
+  fn load(name: &str, contents: &str) -> io::Result<MaterialLibrary> {
+    let path = env::temp_dir().join(format!("rustraylib_{}.mtl", name));
+    fs::write(&path, contents).unwrap();
+    let mut library = MaterialLibrary::new();
+    let result = library.load(path.to_str().unwrap());
+    fs::remove_file(&path).unwrap();
+    result.map(|_| library)
+  }
+
+  #[test]
+  fn comments_can_follow_a_material() {
+    let library = load(
+      "comments",
+      "# a comment line\n\
+       glass tinted 1 1 1 1 1.5 # not an abbe number\n\
+       glass flint 1 1 1 1 1.6 36#dispersive\n\
+       pbr gold 1 0.77 0.34 1 0.2 #\n",
+    )
+    .unwrap();
+    assert_eq!(library.get_names(), vec!["flint", "gold", "tinted"]);
+  }
+
+  #[test]
+  fn extra_words_are_an_error() {
+    assert!(load("extra_words", "glass tinted 1 1 1 1 1.5 tinted\n").is_err());
+  }
+}
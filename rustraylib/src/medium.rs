@@ -0,0 +1,103 @@
+use std::f64;
+
+use color::ColorVector;
+
+fn exp_falloff(sigma: f64, distance: f64) -> f64 {
+  if sigma <= 0.0 {
+    1.0
+  } else {
+    (-sigma * distance).exp()
+  }
+}
+
+// Fog, smoke or the inside of colored glass: a volume that absorbs and scatters light evenly
+// throughout.  Coefficients are per unit of distance and per color channel.
+#[derive(Debug, Copy, Clone)]
+pub struct HomogeneousMedium {
+  pub absorption: ColorVector,
+  pub scattering: ColorVector,
+  pub g: f64, // henyey-greenstein asymmetry, -1 scatters back, 0 evenly, 1 forward
+}
+
+impl HomogeneousMedium {
+  pub fn new(absorption: ColorVector, scattering: ColorVector, g: f64) -> HomogeneousMedium {
+    HomogeneousMedium {
+      absorption,
+      scattering,
+      g,
+    }
+  }
+
+  // mostly scattering, slightly forward like water droplets
+  pub fn new_fog(color: ColorVector, density: f64) -> HomogeneousMedium {
+    HomogeneousMedium::new(
+      ColorVector::new(0.0, 0.0, 0.0),
+      color.multiply_by_scalar(density),
+      0.3,
+    )
+  }
+
+  // only absorbs, e.g. tinted glass or liquids
+  pub fn new_absorbing(absorption: ColorVector) -> HomogeneousMedium {
+    HomogeneousMedium::new(absorption, ColorVector::new(0.0, 0.0, 0.0), 0.0)
+  }
+
+  // beer-lambert absorption given as the color that is left after travelling 1 / density units.
+  // e.g. a color of 0.5 and density 2.0 halves the light every 0.5 units.
+  pub fn new_tinted(color: ColorVector, density: f64) -> HomogeneousMedium {
+    let absorption = |c: f64| -c.clamp(1e-6, 1.0).ln() * density;
+    HomogeneousMedium::new_absorbing(ColorVector::new(
+      absorption(color.r),
+      absorption(color.g),
+      absorption(color.b),
+    ))
+  }
+
+  pub fn get_extinction(&self) -> ColorVector {
+    self.absorption.add(self.scattering)
+  }
+
+  pub fn is_scattering(&self) -> bool {
+    self.scattering.r > 0.0 || self.scattering.g > 0.0 || self.scattering.b > 0.0
+  }
+
+  // fraction of light left after travelling distance through the medium
+  pub fn get_transmittance(&self, distance: f64) -> ColorVector {
+    let sigma = self.get_extinction();
+    ColorVector::new(
+      exp_falloff(sigma.r, distance),
+      exp_falloff(sigma.g, distance),
+      exp_falloff(sigma.b, distance),
+    )
+  }
+
+  // henyey-greenstein phase function.  cos_theta is between the direction light was travelling
+  // in and the one it is scattered into.
+  pub fn phase(&self, cos_theta: f64) -> f64 {
+    let g = self.g.clamp(-0.99, 0.99);
+    let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * f64::consts::PI * denom * denom.sqrt())
+  }
+
+  // picks a distance along a ray of length max_distance (which may be infinite) in proportion to
+  // the average transmittance, returns the distance and its pdf.
+  pub fn sample_distance(&self, u: f64, max_distance: f64) -> Option<(f64, f64)> {
+    let sigma_t = self.get_extinction();
+    let sigma = (sigma_t.r + sigma_t.g + sigma_t.b) / 3.0;
+    if sigma <= 0.0 {
+      if max_distance.is_finite() && max_distance > 0.0 {
+        return Some((u * max_distance, 1.0 / max_distance));
+      }
+      return None;
+    }
+
+    // exponential distribution truncated at max_distance
+    let norm = 1.0 - exp_falloff(sigma, max_distance);
+    if norm <= 0.0 {
+      return None;
+    }
+    let distance = -(1.0 - u * norm).ln() / sigma;
+    let pdf = sigma * (-sigma * distance).exp() / norm;
+    Some((distance, pdf))
+  }
+}
@@ -1,13 +1,18 @@
 use std::sync::Arc;
+use std::io;
 use std::io::BufReader;
 use std::io::BufRead;
 use std::fs::File;
+use std::path::Path;
 
 use scene::{Background, Scene};
 use camera::Camera;
 use renderer::RenderData;
 use shapes::*;
 use material::*;
+use materiallibrary::MaterialLibrary;
+use environment::{Environment, EnvironmentMap};
+use sky::PreethamSky;
 use light::*;
 use color::ColorVector;
 use posvector::PosVector;
@@ -26,6 +31,60 @@ fn as_u32(s: &str) -> u32 {
   s.parse::<u32>().unwrap()
 }
 
+fn as_position(vec: &[&str], i: usize) -> PosVector {
+  PosVector::new(as_f64(vec[i]), as_f64(vec[i + 1]), as_f64(vec[i + 2]))
+}
+
+fn parse_error(file_path: &str, num: usize, message: String) -> io::Error {
+  io::Error::new(
+    io::ErrorKind::InvalidData,
+    format!("{}:{}: {}", file_path, num + 1, message),
+  )
+}
+
+// the optional "r g b" color of a light at start, followed by optional settings:
+//   "intensity" i
+//   "attenuation" none | inverse_square | constant linear quadratic
+fn parse_light_options(vec: &[&str], start: usize) -> Result<(ColorVector, f64, Attenuation), String> {
+  let mut color = ColorVector::new(1.0, 1.0, 1.0);
+  let mut intensity = 1.0;
+  let mut attenuation = Attenuation::None;
+
+  let mut i = start;
+  if vec.len() >= i + 3 && vec[i].parse::<f64>().is_ok() {
+    color = ColorVector::new(as_f64(vec[i]), as_f64(vec[i + 1]), as_f64(vec[i + 2]));
+    i += 3;
+  }
+  while i < vec.len() {
+    match vec[i] {
+      "intensity" => {
+        intensity = as_f64(vec[i + 1]);
+        i += 2;
+      }
+      "attenuation" => match vec[i + 1] {
+        "none" => {
+          attenuation = Attenuation::None;
+          i += 2;
+        }
+        "inverse_square" => {
+          attenuation = Attenuation::InverseSquare;
+          i += 2;
+        }
+        _ => {
+          attenuation = Attenuation::Polynomial {
+            constant: as_f64(vec[i + 1]),
+            linear: as_f64(vec[i + 2]),
+            quadratic: as_f64(vec[i + 3]),
+          };
+          i += 4;
+        }
+      },
+      option => return Err(format!("unknown light option '{}'", option)),
+    }
+  }
+  Ok((color, intensity, attenuation))
+}
+
 enum LookingFor {
   Instruction,
   ViewpointFrom,
@@ -38,7 +97,38 @@ enum LookingFor {
 }
 
 // see: http://www.fileformat.info/format/nff/egff.htm
-pub fn parse_nff_file(file_path: &str, num_threads: u32, ray_trace_depth: u32) -> NffParserResult {
+pub fn parse_nff_file(file_path: &str, num_threads: u32, ray_trace_depth: u32) -> io::Result<NffParserResult> {
+  parse_nff_file_with_materials(
+    file_path,
+    num_threads,
+    ray_trace_depth,
+    MaterialLibrary::new_with_presets(),
+  )
+}
+
+// besides the standard NFF commands, materials can be picked by name from the library:
+//   "m" name           use a named material for the following shapes
+//   "mtllib" path      load more named materials (see MaterialLibrary), relative to the nff file
+// and there are more kinds of lights, all followed by the same optional color and settings as "l":
+//   "ld" x y z                               directional light travelling in direction x y z
+//   "lspot" x y z dx dy dz inner outer       spot light, cone angles in degrees
+//   "lrect" x y z ux uy uz vx vy vz          rectangle centered on x y z with edges u and v
+//   "ldisk" x y z nx ny nz radius            disk facing its normal n
+//   "lsphere" x y z radius
+// e.g. "l 0 0 10 1 1 1 intensity 100 attenuation inverse_square"
+// polygon vertices can be followed by texture coordinates, "x y z u v", for image and normal maps.
+// the background can be replaced by an environment that also lights the scene:
+//   "env" path [intensity [rotation]]   equirectangular .hdr map (see EnvironmentMap), relative to the nff file
+//   "sky" elevation azimuth turbidity [intensity]   daylight sky and its sun (see PreethamSky), angles in degrees
+// unknown material names and light options, and material libraries or environment maps that can't be
+// loaded are returned as errors.
+pub fn parse_nff_file_with_materials(
+  file_path: &str,
+  num_threads: u32,
+  ray_trace_depth: u32,
+  materials: MaterialLibrary,
+) -> io::Result<NffParserResult> {
+  let mut materials = materials;
   let mut shapes: Vec<Box<Shape>> = Vec::new();
   let mut lights: Vec<Box<Light>> = Vec::new();
   let mut camera_from = PosVector::new_default();
@@ -69,25 +159,36 @@ pub fn parse_nff_file(file_path: &str, num_threads: u32, ray_trace_depth: u32) -
   // }));
 
   let mut background = Background::new(ColorVector::new(0.0, 0.0, 0.0), 0.0);
+  let mut environment: Option<Arc<dyn Environment>> = None;
 
   let mut looking_for = LookingFor::Instruction;
-  let mut current_material =
-    SolidMaterial::new(0.0, 0.0, 0.0, 0.0, ColorVector::new(0.0, 0.0, 0.0));
+  // shapes share the current material instead of each getting their own copy
+  let mut current_material: Arc<dyn Material> = Arc::new(PhongMaterial::new(
+    ColorVector::new(0.0, 0.0, 0.0),
+    1.0,
+    0.0,
+    0.0,
+    0.0,
+    1.0,
+  ));
 
   let mut current_shape_id = 1;
   let mut current_item_counter = 0;
   let mut poly_vectors: Vec<PosVector> = Vec::new();
+  let mut poly_uvs: Vec<(f64, f64)> = Vec::new();
 
-  let f = File::open(file_path).unwrap();
+  let f = File::open(file_path)?;
   let file = BufReader::new(&f);
-  for (_num, line) in file.lines().enumerate() {
-    let l = line.unwrap();
+  for (num, line) in file.lines().enumerate() {
+    let l = line?;
 
     match looking_for {
       LookingFor::Instruction => {
         let vec: Vec<&str> = l.split_whitespace().collect();
 
         let instruction = vec[0];
+        let light_options =
+          |start| parse_light_options(&vec, start).map_err(|message| parse_error(file_path, num, message));
 
         if instruction == "b" {
           // background color
@@ -103,17 +204,52 @@ pub fn parse_nff_file(file_path: &str, num_threads: u32, ray_trace_depth: u32) -
         } else if instruction == "l" {
           // println!("reading light: {}", num);
           // positional light
-
-          let mut color_vec = ColorVector::new(1.0, 1.0, 1.0);
-
-          if vec.len() == 7 {
-            color_vec = ColorVector::new(as_f64(vec[4]), as_f64(vec[5]), as_f64(vec[6]));
-          }
-
-          lights.push(Box::new(PointLight::new(
-            PosVector::new(as_f64(vec[1]), as_f64(vec[2]), as_f64(vec[3])), // start at 1 to skip instruction
+          let (color_vec, intensity, attenuation) = light_options(4)?;
+          let mut light = PointLight::new(as_position(&vec, 1), color_vec); // start at 1 to skip instruction
+          light.intensity = intensity;
+          light.attenuation = attenuation;
+          lights.push(Box::new(light));
+        } else if instruction == "ld" {
+          // directional lights are infinitely far away, so they don't attenuate
+          let (color_vec, intensity, _) = light_options(4)?;
+          let mut light = DirectionalLight::new(as_position(&vec, 1), color_vec);
+          light.intensity = intensity;
+          lights.push(Box::new(light));
+        } else if instruction == "lspot" {
+          let (color_vec, intensity, attenuation) = light_options(9)?;
+          let mut light = SpotLight::new(
+            as_position(&vec, 1),
+            as_position(&vec, 4),
             color_vec,
-          )));
+            as_f64(vec[7]),
+            as_f64(vec[8]),
+          );
+          light.intensity = intensity;
+          light.attenuation = attenuation;
+          lights.push(Box::new(light));
+        } else if instruction == "lrect" {
+          let (color_vec, intensity, attenuation) = light_options(10)?;
+          let mut light = RectLight::new(
+            as_position(&vec, 1),
+            as_position(&vec, 4),
+            as_position(&vec, 7),
+            color_vec,
+          );
+          light.intensity = intensity;
+          light.attenuation = attenuation;
+          lights.push(Box::new(light));
+        } else if instruction == "ldisk" {
+          let (color_vec, intensity, attenuation) = light_options(8)?;
+          let mut light = DiskLight::new(as_position(&vec, 1), as_position(&vec, 4), as_f64(vec[7]), color_vec);
+          light.intensity = intensity;
+          light.attenuation = attenuation;
+          lights.push(Box::new(light));
+        } else if instruction == "lsphere" {
+          let (color_vec, intensity, attenuation) = light_options(5)?;
+          let mut light = SphereLight::new(as_position(&vec, 1), as_f64(vec[4]), color_vec);
+          light.intensity = intensity;
+          light.attenuation = attenuation;
+          lights.push(Box::new(light));
         } else if instruction == "f" {
           // println!("reading f: {}", num);
           // object material properties
@@ -124,14 +260,57 @@ pub fn parse_nff_file(file_path: &str, num_threads: u32, ray_trace_depth: u32) -
           // T Transmittance (fraction of contribution of the transmitting ray).
           // Usually, 0 <= Kd <= 1 and 0 <= Ks <= 1, though it is not required that Kd + Ks = 1. Note that transmitting objects (T > 0) are considered to have two sides for algorithms that need these (normally, objects have one side).
 
-          // todo: i don't think i'm assigning the correct values into my solidmaterial yet
-          current_material = SolidMaterial::new(
-            as_f64(vec[6]),
+          current_material = Arc::new(PhongMaterial::new(
+            ColorVector::new(as_f64(vec[1]), as_f64(vec[2]), as_f64(vec[3])),
+            as_f64(vec[4]),
             as_f64(vec[5]),
-            as_f64(vec[8]),
+            as_f64(vec[6]),
             as_f64(vec[7]),
-            ColorVector::new(as_f64(vec[1]), as_f64(vec[2]), as_f64(vec[3])),
-          );
+            as_f64(vec[8]),
+          ));
+        } else if instruction == "m" {
+          // named material from the library
+          current_material = materials
+            .get(vec[1])
+            .ok_or_else(|| parse_error(file_path, num, format!("unknown material '{}'", vec[1])))?;
+        } else if instruction == "mtllib" {
+          let library_path = match Path::new(file_path).parent() {
+            Some(dir) => dir.join(vec[1]),
+            None => Path::new(vec[1]).to_path_buf(),
+          };
+          materials.load(library_path.to_str().unwrap()).map_err(|err| {
+            parse_error(
+              file_path,
+              num,
+              format!("can't load material library {}: {}", library_path.display(), err),
+            )
+          })?;
+        } else if instruction == "env" {
+          let map_path = match Path::new(file_path).parent() {
+            Some(dir) => dir.join(vec[1]),
+            None => Path::new(vec[1]).to_path_buf(),
+          };
+          let mut map = EnvironmentMap::load(map_path.to_str().unwrap()).map_err(|err| {
+            parse_error(
+              file_path,
+              num,
+              format!("can't load environment map {}: {}", map_path.display(), err),
+            )
+          })?;
+          if vec.len() > 2 {
+            map.intensity = as_f64(vec[2]);
+          }
+          if vec.len() > 3 {
+            map.rotation = as_f64(vec[3]);
+          }
+          environment = Some(Arc::new(map));
+        } else if instruction == "sky" {
+          let mut sky = PreethamSky::new(as_f64(vec[1]), as_f64(vec[2]), as_f64(vec[3]));
+          if vec.len() > 4 {
+            sky.intensity = as_f64(vec[4]);
+          }
+          lights.push(Box::new(sky.get_sun()));
+          environment = Some(Arc::new(sky));
         } else if instruction == "c" {
           // cone or cylinder
           // println!("reading c: {}", num);
@@ -142,7 +321,7 @@ pub fn parse_nff_file(file_path: &str, num_threads: u32, ray_trace_depth: u32) -
           shapes.push(Box::new(SphereShape {
             position: PosVector::new(as_f64(vec[1]), as_f64(vec[2]), as_f64(vec[3])),
             radius: as_f64(vec[4]),
-            material: Arc::new(current_material),
+            material: current_material.clone(),
             id: current_shape_id,
           }));
           current_shape_id = current_shape_id + 1;
@@ -151,6 +330,7 @@ pub fn parse_nff_file(file_path: &str, num_threads: u32, ray_trace_depth: u32) -
           // polygon
           current_item_counter = as_u32(vec[1]);
           poly_vectors = Vec::new();
+          poly_uvs = Vec::new();
 
           looking_for = LookingFor::Polygon;
         } else if instruction == "pp" {
@@ -175,39 +355,29 @@ pub fn parse_nff_file(file_path: &str, num_threads: u32, ray_trace_depth: u32) -
             as_f64(vec[1]),
             as_f64(vec[2]),
           ));
+          if vec.len() >= 5 {
+            poly_uvs.push((as_f64(vec[3]), as_f64(vec[4])));
+          }
         }
 
         if current_item_counter == 0 {
-          if poly_vectors.len() >= 3 {
-            let mut first_vert = poly_vectors[0];
-            let mut prev_vert = poly_vectors[1];
-            let mut this_vert = poly_vectors[2];
-
-            shapes.push(Box::new(TriangleShape::new(
-              first_vert,
-              prev_vert,
-              this_vert,
-              Arc::new(current_material),
-              Arc::new(current_material),
+          // uvs are only used when every vertex has them
+          let has_uvs = poly_uvs.len() == poly_vectors.len();
+          // fan out from the first vertex
+          for i in 2..poly_vectors.len() {
+            let mut triangle = TriangleShape::new(
+              poly_vectors[0],
+              poly_vectors[i - 1],
+              poly_vectors[i],
+              current_material.clone(),
+              current_material.clone(),
               current_shape_id,
-            )));
-            current_shape_id = current_shape_id + 1;
-
-            for i in 3..poly_vectors.len() {
-              prev_vert = this_vert;
-
-              this_vert = poly_vectors[i];
-
-              shapes.push(Box::new(TriangleShape::new(
-                first_vert,
-                prev_vert,
-                this_vert,
-                Arc::new(current_material),
-                Arc::new(current_material),
-                current_shape_id,
-              )));
-              current_shape_id = current_shape_id + 1;
+            );
+            if has_uvs {
+              triangle.set_uvs(poly_uvs[0], poly_uvs[i - 1], poly_uvs[i]);
             }
+            shapes.push(Box::new(triangle));
+            current_shape_id += 1;
           }
 
           looking_for = LookingFor::Instruction;
@@ -259,12 +429,11 @@ pub fn parse_nff_file(file_path: &str, num_threads: u32, ray_trace_depth: u32) -
     }
   }
 
-  NffParserResult {
-    scene: Scene::new(
-      background,
-      shapes,
-      lights
-    ),
+  let mut scene = Scene::new(background, shapes, lights);
+  scene.environment = environment;
+
+  Ok(NffParserResult {
+    scene,
     render_data: RenderData::new(
       resolution_x,
       resolution_y,
@@ -273,5 +442,57 @@ pub fn parse_nff_file(file_path: &str, num_threads: u32, ray_trace_depth: u32) -
       true,
     ),
     camera: Camera::new(camera_from, camera_at, camera_up, 50.0),
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+  use std::fs;
+
+  // writes the scene to a temporary file and parses it
+  fn parse(name: &str, contents: &str) -> io::Result<NffParserResult> {
+    let path = env::temp_dir().join(format!("rustraylib_{}.nff", name));
+    fs::write(&path, contents).unwrap();
+    let result = parse_nff_file(path.to_str().unwrap(), 1, 1);
+    fs::remove_file(&path).unwrap();
+    result
+  }
+
+  fn get_error(result: io::Result<NffParserResult>) -> String {
+    match result {
+      Ok(_) => panic!("the scene should not parse"),
+      Err(err) => {
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        err.to_string()
+      }
+    }
+  }
+
+  #[test]
+  fn named_materials_are_looked_up() {
+    assert!(parse("known_material", "m gold\ns 0 0 0 1\n").is_ok());
+    let err = get_error(parse("unknown_material", "b 0 0 0\nm unobtainium\ns 0 0 0 1\n"));
+    assert!(err.contains(":2: unknown material 'unobtainium'"), "{}", err);
+  }
+
+  #[test]
+  fn missing_material_library_is_an_error() {
+    let err = get_error(parse("missing_mtllib", "mtllib does_not_exist.mtl\n"));
+    assert!(err.contains("can't load material library"), "{}", err);
+  }
+
+  #[test]
+  fn missing_environment_map_is_an_error() {
+    let err = get_error(parse("missing_env", "env does_not_exist.hdr 2\n"));
+    assert!(err.contains(":1: can't load environment map"), "{}", err);
+  }
+
+  #[test]
+  fn unknown_light_option_is_an_error() {
+    assert!(parse("light_options", "l 0 0 10 1 1 1 intensity 100 attenuation inverse_square\n").is_ok());
+    let err = get_error(parse("unknown_light_option", "l 0 0 10 1 1 1 brightness 100\n"));
+    assert!(err.contains(":1: unknown light option 'brightness'"), "{}", err);
   }
 }
@@ -0,0 +1,215 @@
+use std::f64;
+
+use color::ColorVector;
+use material::SurfaceCoords;
+use posvector::PosVector;
+use texture::Texture;
+
+// Ken Perlin's reference permutation of 0..255
+const PERMUTATION: [u8; 256] = [
+  151, 160, 137, 91, 90, 15, 131, 13, 201, 95, 96, 53, 194, 233, 7, 225, 140, 36, 103, 30, 69,
+  142, 8, 99, 37, 240, 21, 10, 23, 190, 6, 148, 247, 120, 234, 75, 0, 26, 197, 62, 94, 252, 219,
+  203, 117, 35, 11, 32, 57, 177, 33, 88, 237, 149, 56, 87, 174, 20, 125, 136, 171, 168, 68, 175,
+  74, 165, 71, 134, 139, 48, 27, 166, 77, 146, 158, 231, 83, 111, 229, 122, 60, 211, 133, 230, 220,
+  105, 92, 41, 55, 46, 245, 40, 244, 102, 143, 54, 65, 25, 63, 161, 1, 216, 80, 73, 209, 76, 132,
+  187, 208, 89, 18, 169, 200, 196, 135, 130, 116, 188, 159, 86, 164, 100, 109, 198, 173, 186, 3,
+  64, 52, 217, 226, 250, 124, 123, 5, 202, 38, 147, 118, 126, 255, 82, 85, 212, 207, 206, 59, 227,
+  47, 16, 58, 17, 182, 189, 28, 42, 223, 183, 170, 213, 119, 248, 152, 2, 44, 154, 163, 70, 221,
+  153, 101, 155, 167, 43, 172, 9, 129, 22, 39, 253, 19, 98, 108, 110, 79, 113, 224, 232, 178, 185,
+  112, 104, 218, 246, 97, 228, 251, 34, 242, 193, 238, 210, 144, 12, 191, 179, 162, 241, 81, 51,
+  145, 235, 249, 14, 239, 107, 49, 192, 214, 31, 181, 199, 106, 157, 184, 84, 204, 176, 115, 121,
+  50, 45, 127, 4, 150, 254, 138, 236, 205, 93, 222, 114, 67, 29, 24, 72, 243, 141, 128, 195, 78,
+  66, 215, 61, 156, 180,
+];
+
+fn perm(i: usize) -> usize {
+  PERMUTATION[i & 255] as usize
+}
+
+fn fade(t: f64) -> f64 {
+  t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+  a + t * (b - a)
+}
+
+// dot product of (x, y, z) with one of the 12 gradient directions picked by the hash
+fn grad(hash: usize, x: f64, y: f64, z: f64) -> f64 {
+  let h = hash & 15;
+  let u = if h < 8 { x } else { y };
+  let v = if h < 4 {
+    y
+  } else if h == 12 || h == 14 {
+    x
+  } else {
+    z
+  };
+  (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+// improved gradient noise, roughly in [-1,1]
+pub fn noise(p: PosVector) -> f64 {
+  let xf = p.x.floor();
+  let yf = p.y.floor();
+  let zf = p.z.floor();
+
+  let xi = (xf as i64 & 255) as usize;
+  let yi = (yf as i64 & 255) as usize;
+  let zi = (zf as i64 & 255) as usize;
+
+  let x = p.x - xf;
+  let y = p.y - yf;
+  let z = p.z - zf;
+
+  let u = fade(x);
+  let v = fade(y);
+  let w = fade(z);
+
+  let a = perm(xi) + yi;
+  let aa = perm(a) + zi;
+  let ab = perm(a + 1) + zi;
+  let b = perm(xi + 1) + yi;
+  let ba = perm(b) + zi;
+  let bb = perm(b + 1) + zi;
+
+  lerp(
+    w,
+    lerp(
+      v,
+      lerp(u, grad(perm(aa), x, y, z), grad(perm(ba), x - 1.0, y, z)),
+      lerp(u, grad(perm(ab), x, y - 1.0, z), grad(perm(bb), x - 1.0, y - 1.0, z)),
+    ),
+    lerp(
+      v,
+      lerp(
+        u,
+        grad(perm(aa + 1), x, y, z - 1.0),
+        grad(perm(ba + 1), x - 1.0, y, z - 1.0),
+      ),
+      lerp(
+        u,
+        grad(perm(ab + 1), x, y - 1.0, z - 1.0),
+        grad(perm(bb + 1), x - 1.0, y - 1.0, z - 1.0),
+      ),
+    ),
+  )
+}
+
+// fractional brownian motion: octaves of noise, each at twice the frequency and half the amplitude
+pub fn fbm(p: PosVector, octaves: u32) -> f64 {
+  let mut sum = 0.0;
+  let mut frequency = 1.0;
+  let mut amplitude = 1.0;
+  for _ in 0..octaves {
+    sum += amplitude * noise(p.multiply_by_scalar(frequency));
+    frequency *= 2.0;
+    amplitude *= 0.5;
+  }
+  sum
+}
+
+// like fbm but summing the absolute value of each octave, which gives sharp creases
+pub fn turbulence(p: PosVector, octaves: u32) -> f64 {
+  let mut sum = 0.0;
+  let mut frequency = 1.0;
+  let mut amplitude = 1.0;
+  for _ in 0..octaves {
+    sum += amplitude * noise(p.multiply_by_scalar(frequency)).abs();
+    frequency *= 2.0;
+    amplitude *= 0.5;
+  }
+  sum
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum NoisePattern {
+  Noise,
+  Turbulence,
+  Marble,
+  Wood,
+  Granite,
+  Clouds,
+}
+
+// which space the noise is evaluated in.  object space moves with the shape,
+// world space lets a pattern run continuously across several shapes.
+#[derive(Debug, Copy, Clone)]
+pub enum NoiseSpace {
+  Object,
+  World,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct NoiseTexture {
+  pub pattern: NoisePattern,
+  pub space: NoiseSpace,
+  pub color_a: ColorVector,
+  pub color_b: ColorVector,
+  pub scale: f64,
+  pub octaves: u32,
+}
+
+impl NoiseTexture {
+  pub fn new(pattern: NoisePattern, color_a: ColorVector, color_b: ColorVector, scale: f64) -> NoiseTexture {
+    NoiseTexture {
+      pattern,
+      space: NoiseSpace::Object,
+      color_a,
+      color_b,
+      scale,
+      octaves: 6,
+    }
+  }
+
+  pub fn new_marble(vein_color: ColorVector, base_color: ColorVector, scale: f64) -> NoiseTexture {
+    NoiseTexture::new(NoisePattern::Marble, vein_color, base_color, scale)
+  }
+
+  pub fn new_wood(dark_color: ColorVector, light_color: ColorVector, scale: f64) -> NoiseTexture {
+    NoiseTexture::new(NoisePattern::Wood, dark_color, light_color, scale)
+  }
+
+  pub fn new_granite(dark_color: ColorVector, light_color: ColorVector, scale: f64) -> NoiseTexture {
+    NoiseTexture::new(NoisePattern::Granite, dark_color, light_color, scale)
+  }
+
+  pub fn new_clouds(sky_color: ColorVector, cloud_color: ColorVector, scale: f64) -> NoiseTexture {
+    NoiseTexture::new(NoisePattern::Clouds, sky_color, cloud_color, scale)
+  }
+
+  // how far between color_a (0.0) and color_b (1.0) the pattern is at p
+  pub fn get_value(&self, p: PosVector) -> f64 {
+    let p = p.multiply_by_scalar(self.scale);
+    let t = match self.pattern {
+      NoisePattern::Noise => 0.5 + 0.5 * noise(p),
+      NoisePattern::Turbulence => turbulence(p, self.octaves),
+      NoisePattern::Marble => {
+        0.5 + 0.5 * (2.0 * f64::consts::PI * p.x + 4.0 * turbulence(p, self.octaves)).sin()
+      }
+      NoisePattern::Wood => {
+        // concentric rings around the z axis, wobbled by low frequency noise
+        let rings = (p.x * p.x + p.y * p.y).sqrt() + 0.5 * noise(p.multiply_by_scalar(0.5));
+        let grain = rings * 4.0;
+        grain - grain.floor()
+      }
+      NoisePattern::Granite => (2.5 * turbulence(p.multiply_by_scalar(4.0), self.octaves)).min(1.0),
+      NoisePattern::Clouds => 0.5 + 0.5 * fbm(p, self.octaves),
+    };
+    t.clamp(0.0, 1.0)
+  }
+
+  pub fn get_color(&self, coords: &SurfaceCoords) -> ColorVector {
+    let p = match self.space {
+      NoiseSpace::Object => coords.object_position,
+      NoiseSpace::World => coords.position,
+    };
+    self.color_a.blend(self.color_b, self.get_value(p))
+  }
+}
+
+impl Texture for NoiseTexture {
+  fn get_value(&self, coords: &SurfaceCoords) -> ColorVector {
+    self.get_color(coords)
+  }
+}
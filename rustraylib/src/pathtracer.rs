@@ -0,0 +1,277 @@
+use std::f64;
+
+use bsdf::Bsdf;
+use camera::Ray;
+use color::ColorVector;
+use integrator::Integrator;
+use medium::HomogeneousMedium;
+use posvector::PosVector;
+use sampling;
+use spectral;
+use tracer::{IntersectionInfo, RayTracer};
+
+// bounces a path takes before russian roulette may end it
+const RUSSIAN_ROULETTE_DEPTH: u32 = 3;
+
+// Unidirectional path tracing, what RenderMode::PathTraced renders.  The camera ray bounces around the
+// scene, picking each new direction from the bsdf, and at every bounce picks up the light that reaches
+// it directly.  Unlike WhittedIntegrator this includes indirect light, light bouncing off diffuse
+// surfaces onto others.  The classic materials get a bsdf from their phong parameters, their
+// transparency and reflection pick glass, mirror or that bsdf for each bounce: glass with the
+// transparency, a mirror with the reflection of what's left, the bsdf otherwise.
+#[derive(Debug, Default)]
+pub struct PathIntegrator {}
+
+impl PathIntegrator {
+  pub fn new() -> PathIntegrator {
+    PathIntegrator {}
+  }
+
+  // light reaching a path vertex straight from the lights, emissive shapes and the environment, with
+  // one shadow ray each.  wo points back along the path and n is the shading normal on its side.
+  // emitters and the environment can also be hit by the next bounce, both estimates are combined
+  // with multiple importance sampling.  the lights can't be hit, they're counted in full here.
+  #[allow(clippy::too_many_arguments)]
+  fn sample_direct_light(
+    &self,
+    tracer: &RayTracer,
+    intersection_info: &IntersectionInfo,
+    ray: &Ray,
+    n: PosVector,
+    wo: PosVector,
+    bsdf: &Bsdf,
+    medium: Option<HomogeneousMedium>,
+  ) -> ColorVector {
+    let mut color = ColorVector::new(0.0, 0.0, 0.0);
+
+    for (light, weight) in tracer.pick_lights(intersection_info.position, n) {
+      let sample = light.sample(intersection_info.position, sampling::next_f64(), sampling::next_f64());
+      let cos_surface = n.dot_product(sample.direction);
+      if sample.distance <= 0.0 || cos_surface <= 0.0 {
+        continue;
+      }
+      let transmittance =
+        tracer.get_shadow_transmittance(intersection_info, ray, sample.direction, sample.distance, medium);
+      // lights are scaled by pi, see the light units in light.rs
+      color = color.add(
+        bsdf
+          .eval(n, wo, sample.direction)
+          .multiply(sample.color)
+          .multiply(transmittance)
+          .multiply_by_scalar(cos_surface * f64::consts::PI * weight),
+      );
+    }
+
+    if let Some(emitter_color) = self.sample_emitter(tracer, intersection_info, ray, n, wo, bsdf, medium) {
+      color = color.add(emitter_color);
+    }
+
+    if let Some(ref environment) = tracer.scene.environment {
+      if let Some(sample) = environment.sample(sampling::next_f64(), sampling::next_f64()) {
+        let wi = sample.direction;
+        let cos_surface = n.dot_product(wi);
+        if cos_surface > 0.0 {
+          let mis_weight = sampling::power_heuristic(sample.pdf, bsdf.pdf(n, wo, wi));
+          let transmittance =
+            tracer.get_shadow_transmittance(intersection_info, ray, wi, f64::INFINITY, medium);
+          color = color.add(
+            spectral::upsample(sample.radiance)
+              .multiply(bsdf.eval(n, wo, wi))
+              .multiply(transmittance)
+              .multiply_by_scalar(cos_surface * mis_weight / sample.pdf),
+          );
+        }
+      }
+    }
+
+    color
+  }
+
+  // light from a single emissive shape, picked in proportion to its power
+  #[allow(clippy::too_many_arguments)]
+  fn sample_emitter(
+    &self,
+    tracer: &RayTracer,
+    intersection_info: &IntersectionInfo,
+    ray: &Ray,
+    n: PosVector,
+    wo: PosVector,
+    bsdf: &Bsdf,
+    medium: Option<HomogeneousMedium>,
+  ) -> Option<ColorVector> {
+    let (emitter_id, pick_pdf, u1) = tracer.scene.light_sampler.sample_emitter(sampling::next_f64())?;
+    if emitter_id == intersection_info.element_id {
+      return None;
+    }
+    let emitter = tracer.scene.get_shape(&emitter_id)?;
+    let sample = emitter.sample_surface(u1, sampling::next_f64())?;
+    let to_light = sample.coords.position.subtract(intersection_info.position);
+    let distance = to_light.magnitude();
+    if distance <= 0.0 {
+      return None;
+    }
+    let wi = to_light.divide_by_scalar(distance);
+    let cos_surface = n.dot_product(wi);
+    let cos_light = sample.normal.dot_product(wi).abs();
+    if cos_surface <= 0.0 || cos_light <= 0.0 {
+      return None;
+    }
+    let light_pdf = pick_pdf * distance * distance / (cos_light * emitter.get_area());
+    let mis_weight = sampling::power_heuristic(light_pdf, bsdf.pdf(n, wo, wi));
+    let transmittance = tracer.get_shadow_transmittance(intersection_info, ray, wi, distance, medium);
+    Some(
+      emitter
+        .get_material()
+        .get_emission(&sample.coords)
+        .multiply(bsdf.eval(n, wo, wi))
+        .multiply(transmittance)
+        .multiply_by_scalar(cos_surface * mis_weight / light_pdf),
+    )
+  }
+
+  fn trace_path(&self, tracer: &RayTracer, camera_ray: &Ray) -> ColorVector {
+    let mut color = ColorVector::new(0.0, 0.0, 0.0);
+    let mut throughput = ColorVector::new(1.0, 1.0, 1.0);
+    let mut ray = Ray::new(camera_ray.get_position(), camera_ray.get_direction());
+    let mut medium = tracer.get_scene_medium();
+    // pdf of the bsdf sampled bounce that led to the current ray, None if emission along the ray
+    // can't also have been found by sample_direct_light (the camera ray, mirror and glass bounces)
+    let mut bsdf_pdf: Option<f64> = None;
+    let mut depth = 0;
+
+    loop {
+      let info = tracer.test_shading_intersection(&ray, 0);
+      let is_hit = info.is_hit && info.distance > 0.0;
+
+      if let Some(ref medium) = medium {
+        let distance = if is_hit { info.distance } else { f64::INFINITY };
+        let black = ColorVector::new(0.0, 0.0, 0.0);
+        color = color.add(throughput.multiply(tracer.render_medium(black, &ray, distance, medium, 1)));
+        throughput = throughput.multiply(medium.get_transmittance(distance));
+      }
+
+      if !is_hit {
+        let direction = ray.get_direction().normalize();
+        let background = match tracer.scene.environment {
+          Some(ref environment) => {
+            let mis_weight = match bsdf_pdf {
+              Some(pdf) => sampling::power_heuristic(pdf, environment.pdf(direction)),
+              None => 1.0,
+            };
+            environment.get_radiance(direction).multiply_by_scalar(mis_weight)
+          }
+          None => tracer.scene.background.color,
+        };
+        color = color.add(throughput.multiply(spectral::upsample(background)));
+        break;
+      }
+
+      let elem = match tracer.scene.get_shape(&info.element_id) {
+        Some(elem) => elem,
+        None => break,
+      };
+      let material = elem.get_material();
+      let coords = &info.coords;
+      let v = ray.get_direction();
+      let wo = v.multiply_by_scalar(-1.0);
+
+      let emission = material.get_emission(coords);
+      if emission.r > 0.0 || emission.g > 0.0 || emission.b > 0.0 {
+        let cos_light = info.geometric_normal.dot_product(wo).abs();
+        let mis_weight = match bsdf_pdf {
+          Some(pdf) if elem.is_emitter() && cos_light > 0.0 => {
+            let pick_pdf = tracer.scene.light_sampler.get_emitter_pdf(elem.get_id());
+            let light_pdf = pick_pdf * info.distance * info.distance / (cos_light * elem.get_area());
+            sampling::power_heuristic(pdf, light_pdf)
+          }
+          _ => 1.0,
+        };
+        color = color.add(throughput.multiply(emission).multiply_by_scalar(mis_weight));
+      }
+
+      if depth >= tracer.render_data.path_depth {
+        break;
+      }
+      depth += 1;
+
+      let gn = info.geometric_normal;
+      let bsdf = material.get_bsdf(coords);
+      let transparency = if bsdf.is_some() { 0.0 } else { material.get_transparency(coords) };
+      let reflection = if bsdf.is_some() { 0.0 } else { material.get_reflection(coords) };
+
+      let u_bounce = sampling::next_f64();
+      if u_bounce < transparency {
+        // glass, the same fresnel split as WhittedIntegrator::render_refraction but following only one
+        // of the two rays
+        let ior = spectral::get_refraction(&*material, coords).max(1.0);
+        let entering = v.dot_product(gn) < 0.0;
+        let (n, n1, n2) = if entering {
+          (info.normal, 1.0, ior)
+        } else {
+          (info.normal.multiply_by_scalar(-1.0), ior, 1.0)
+        };
+        let reflectance = tracer.get_fresnel_reflectance((-n.dot_product(v)).max(0.0), n1, n2);
+        let refraction = tracer.get_refraction_direction(n, v, n1 / n2);
+        ray = match refraction {
+          Some(t) if sampling::next_f64() >= reflectance => {
+            medium = if entering { material.get_medium() } else { tracer.get_scene_medium() };
+            tracer.offset_ray(info.position, gn, t)
+          }
+          _ => {
+            let reflection_ray = tracer.get_reflection_ray(info.position, n, v);
+            tracer.offset_ray(info.position, gn, reflection_ray.get_direction())
+          }
+        };
+        bsdf_pdf = None;
+      } else if u_bounce < transparency + (1.0 - transparency) * reflection {
+        let reflection_ray = tracer.get_reflection_ray(info.position, info.normal, v);
+        ray = tracer.offset_ray(info.position, gn, reflection_ray.get_direction());
+        bsdf_pdf = None;
+      } else {
+        let bsdf = bsdf.unwrap_or_else(|| {
+          Bsdf::new_phong(
+            info.color,
+            material.get_diffuse(coords),
+            material.get_specular(coords),
+            material.get_shininess(coords),
+          )
+        });
+        // the side of the surface the path arrived at
+        let n = if info.normal.dot_product(wo) < 0.0 {
+          info.normal.multiply_by_scalar(-1.0)
+        } else {
+          info.normal
+        };
+
+        let direct_light = self.sample_direct_light(tracer, &info, &ray, n, wo, &bsdf, medium);
+        color = color.add(throughput.multiply(direct_light));
+
+        let (u_lobe, u1, u2) = (sampling::next_f64(), sampling::next_f64(), sampling::next_f64());
+        let sample = match bsdf.sample(n, wo, u_lobe, u1, u2) {
+          Some(sample) => sample,
+          None => break,
+        };
+        throughput = throughput.multiply(sample.weight);
+        bsdf_pdf = if sample.is_specular { None } else { Some(sample.pdf) };
+        ray = tracer.offset_ray(info.position, gn, sample.direction);
+      }
+
+      // ends paths that carry little light, the ones that go on make up for the others
+      if depth >= RUSSIAN_ROULETTE_DEPTH {
+        let survival = throughput.r.max(throughput.g).max(throughput.b).min(0.95);
+        if survival <= 0.0 || sampling::next_f64() >= survival {
+          break;
+        }
+        throughput = throughput.multiply_by_scalar(1.0 / survival);
+      }
+    }
+
+    color
+  }
+}
+
+impl Integrator for PathIntegrator {
+  fn get_radiance(&self, tracer: &RayTracer, ray: &Ray) -> ColorVector {
+    self.trace_path(tracer, ray)
+  }
+}
@@ -3,6 +3,8 @@ use std::sync::{Arc, Mutex};
 
 use camera::Camera;
 use color::ColorVector;
+use integrator::{new_integrator, Integrator};
+use lightsampling::LightSampling;
 use scene::Scene;
 use threading::ThreadPool;
 use tracer::RayTracer;
@@ -51,6 +53,18 @@ impl PixelArray {
   }
 }
 
+// What the renderer writes for every pixel.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RenderMode {
+  // the shaded image, see WhittedIntegrator
+  Shaded,
+  // grayscale ambient occlusion of the surfaces the camera sees, white where it sees nothing.
+  // for clay renders and checking contact shadows.
+  AmbientOcclusion,
+  // global illumination with a path tracer, see PathIntegrator
+  PathTraced,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct RenderData {
   pub width: u32,
@@ -58,11 +72,20 @@ pub struct RenderData {
   pub ray_trace_depth: u32,
   pub num_threads: u32,
   pub thread_per_line: bool,
-  pub render_diffuse: bool,
-  pub render_reflection: bool,
-  pub render_refraction: bool,
-  pub render_shadow: bool,
-  pub render_highlights: bool
+  pub render_mode: RenderMode,
+  pub ambient_occlusion: bool, // darken the ambient light by the ambient occlusion
+  pub ao_samples: u32, // rays per ambient occlusion estimate, see RayTracer::get_ambient_occlusion
+  pub ao_radius: f64, // only surfaces closer than this occlude
+  pub samples_per_pixel: u32, // jittered rays per pixel, which the path tracer needs many of
+  pub path_depth: u32, // bounces before a path is cut off, russian roulette ends most of them sooner
+  pub light_sampling: LightSampling, // which lights shade a point
+  pub light_samples: u32, // lights picked per shading point, unless light_sampling is All
+  pub glossy_samples: u32, // rays per glossy reflection, see WhittedIntegrator::render_glossy_reflection
+  pub area_light_samples: u32, // shadow rays per area light or emissive shape, see WhittedIntegrator::render_area_light
+  pub environment_samples: u32, // shadow rays towards the environment, see WhittedIntegrator::render_environment
+  pub medium_samples: u32, // scattering points per ray through a medium, see RayTracer::render_medium
+  pub spectral: bool, // trace wavelengths instead of rgb, for dispersion
+  pub wavelength_samples: u32, // wavelengths per pixel in spectral mode
 }
 
 impl RenderData {
@@ -70,7 +93,7 @@ impl RenderData {
                 ray_trace_depth: u32,
                 num_threads: u32,
                 thread_per_line: bool) -> RenderData {
-    RenderData{width, height, ray_trace_depth, num_threads, thread_per_line, render_diffuse: true, render_reflection: true, render_refraction: true, render_shadow: true, render_highlights: true }
+    RenderData{width, height, ray_trace_depth, num_threads, thread_per_line, render_mode: RenderMode::Shaded, ambient_occlusion: false, ao_samples: 16, ao_radius: 1.0, samples_per_pixel: 1, path_depth: 16, light_sampling: LightSampling::All, light_samples: 4, glossy_samples: 8, area_light_samples: 8, environment_samples: 16, medium_samples: 4, spectral: false, wavelength_samples: 16 }
   }
 }
 
@@ -102,6 +125,18 @@ impl Renderer {
     render_data: RenderData,
     scene: Arc<Scene>,
     output_file_path: &str,
+  ) {
+    let integrator = new_integrator(render_data.render_mode);
+    Renderer::render_frame_with_integrator(camera, render_data, scene, integrator, output_file_path);
+  }
+
+  // renders with an integrator of your own instead of the one for render_data.render_mode
+  pub fn render_frame_with_integrator(
+    camera: Camera,
+    render_data: RenderData,
+    scene: Arc<Scene>,
+    integrator: Arc<dyn Integrator>,
+    output_file_path: &str,
   ) {
     // println!("Scene: {:?}", scene);
     // println!();
@@ -109,8 +144,10 @@ impl Renderer {
     println!();
     println!("Camera: {:?}", camera);
     println!();
+    println!("Integrator: {:?}", integrator);
+    println!();
 
-    let pixel_array = Renderer::render(camera, scene, render_data);
+    let pixel_array = Renderer::render(camera, scene, render_data, integrator);
     pixel_array.lock().unwrap().save_as_png(output_file_path);
   }
 
@@ -118,13 +155,14 @@ impl Renderer {
     camera: Camera,
     scene: Arc<Scene>,
     render_data: RenderData,
+    integrator: Arc<dyn Integrator>,
   ) -> Arc<Mutex<PixelArray>> {
     let pixels = Arc::new(Mutex::new(PixelArray::new(
       render_data.width,
       render_data.height,
     )));
 
-    let tracer = RayTracer::new(camera, render_data, scene);
+    let tracer = RayTracer::new_with_integrator(camera, render_data, scene, integrator);
 
     for y in 0..render_data.height {
       for x in 0..render_data.width {
@@ -142,13 +180,14 @@ impl Renderer {
     camera: Camera,
     scene: Arc<Scene>,
     render_data: RenderData,
+    integrator: Arc<dyn Integrator>,
   ) -> Arc<Mutex<PixelArray>> {
     let pixels = Arc::new(Mutex::new(PixelArray::new(
       render_data.width,
       render_data.height,
     )));
 
-    let tracer = Arc::new(RayTracer::new(camera, render_data.clone(), scene));
+    let tracer = Arc::new(RayTracer::new_with_integrator(camera, render_data, scene, integrator));
 
     let pool = ThreadPool::new(render_data.num_threads as usize);
 
@@ -186,11 +225,16 @@ impl Renderer {
     pixels
   }
 
-  fn render(camera: Camera, scene: Arc<Scene>, render_data: RenderData) -> Arc<Mutex<PixelArray>> {
+  fn render(
+    camera: Camera,
+    scene: Arc<Scene>,
+    render_data: RenderData,
+    integrator: Arc<dyn Integrator>,
+  ) -> Arc<Mutex<PixelArray>> {
     if render_data.num_threads <= 1 {
-      Renderer::render_single_threaded(camera, scene, render_data)
+      Renderer::render_single_threaded(camera, scene, render_data, integrator)
     } else {
-      Renderer::render_multi_threaded(camera, scene, render_data)
+      Renderer::render_multi_threaded(camera, scene, render_data, integrator)
     }
   }
 }
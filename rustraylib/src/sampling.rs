@@ -0,0 +1,109 @@
+use std::cell::Cell;
+use std::f64;
+
+use posvector::PosVector;
+
+// Random numbers for the stochastic parts of the tracer (glossy bounces, soft shadows, ...).
+// Each render thread has its own xorshift state, reseeded per pixel so images don't depend
+// on which thread happened to render which pixel.
+thread_local! {
+  static RNG_STATE: Cell<u64> = const { Cell::new(0x853c_49e6_748f_ea9b) };
+}
+
+pub fn seed(seed: u64) {
+  // splitmix64 so that neighbouring seeds give unrelated streams
+  let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+  z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+  z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+  z = z ^ (z >> 31);
+  RNG_STATE.with(|state| state.set(if z == 0 { 1 } else { z }));
+}
+
+// the state of the current thread's stream, set_state goes back to it to replay the same numbers
+pub fn get_state() -> u64 {
+  RNG_STATE.with(|state| state.get())
+}
+
+pub fn set_state(seed_state: u64) {
+  RNG_STATE.with(|state| state.set(seed_state));
+}
+
+// uniform in [0, 1)
+pub fn next_f64() -> f64 {
+  RNG_STATE.with(|state| {
+    let mut x = state.get();
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    state.set(x);
+    (x >> 11) as f64 / (1u64 << 53) as f64
+  })
+}
+
+// two orthonormal vectors perpendicular to the unit vector n
+pub fn build_basis(n: PosVector) -> (PosVector, PosVector) {
+  let helper = if n.x.abs() > 0.9 {
+    PosVector::new_unit_y()
+  } else {
+    PosVector::new_unit_x()
+  };
+  let t = helper.cross(n).normalize();
+  let b = n.cross(t);
+  (t, b)
+}
+
+// takes a direction given in the (t, b, n) frame around n to world space
+pub fn to_world(local: PosVector, n: PosVector) -> PosVector {
+  let (t, b) = build_basis(n);
+  t.multiply_by_scalar(local.x)
+    .add(b.multiply_by_scalar(local.y))
+    .add(n.multiply_by_scalar(local.z))
+}
+
+// multiple importance sampling weight of a sample taken with pdf_f, when it could also have been
+// taken by a strategy with pdf_g (veach's power heuristic with beta 2)
+pub fn power_heuristic(pdf_f: f64, pdf_g: f64) -> f64 {
+  let f = pdf_f * pdf_f;
+  let g = pdf_g * pdf_g;
+  if f + g <= 0.0 {
+    0.0
+  } else {
+    f / (f + g)
+  }
+}
+
+// cosine weighted direction around +z, pdf is cos(theta) / pi
+pub fn cosine_sample_hemisphere(u1: f64, u2: f64) -> PosVector {
+  let r = u1.sqrt();
+  let phi = 2.0 * f64::consts::PI * u2;
+  PosVector::new(r * phi.cos(), r * phi.sin(), (1.0 - u1).max(0.0).sqrt())
+}
+
+// uniform direction on the unit sphere, pdf is 1 / (4 pi)
+pub fn uniform_sample_sphere(u1: f64, u2: f64) -> PosVector {
+  let z = 1.0 - 2.0 * u1;
+  let r = (1.0 - z * z).max(0.0).sqrt();
+  let phi = 2.0 * f64::consts::PI * u2;
+  PosVector::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+// uniform barycentric coordinates (b1, b2) on a triangle, the first vertex gets 1 - b1 - b2
+pub fn uniform_sample_triangle(u1: f64, u2: f64) -> (f64, f64) {
+  let su = u1.sqrt();
+  (u2 * su, su * (1.0 - u2))
+}
+
+// jittered point in [0, 1)^2 for sample index out of count, each one in its own cell of a grid that
+// is as square as count allows
+pub fn stratified_sample(index: u32, count: u32) -> (f64, f64) {
+  let mut rows = ((count as f64).sqrt() as u32).max(1);
+  while !count.is_multiple_of(rows) {
+    rows -= 1;
+  }
+  let cols = count / rows;
+  let (row, col) = (index / cols, index % cols);
+  (
+    (col as f64 + next_f64()) / cols as f64,
+    (row as f64 + next_f64()) / rows as f64,
+  )
+}
@@ -2,9 +2,14 @@ use std::sync::Arc;
 use std::collections::HashMap;
 use elapsed::measure_time;
 
-use material::{BaseMaterial, ChessboardMaterial, Material, SolidMaterial};
-use light::{Light, PointLight};
-use shapes::{BoundingBox, PlaneShape, Shape, SphereShape};
+use material::{Material, SolidMaterial};
+use materiallibrary::MaterialLibrary;
+use light::{Light, LightSample, PointLight};
+use environment::Environment;
+use lightsampling::LightSampler;
+use medium::HomogeneousMedium;
+use spectral;
+use shapes::{BoundingBox, PlaneShape, Shape, SphereShape, SurfaceSample};
 use posvector::PosVector;
 use color::ColorVector;
 use tracer::IntersectionInfo;
@@ -46,14 +51,49 @@ impl CompiledShape {
     intersect_info
   }
 
+  // bump and normal maps are only evaluated for the closest hit, not every candidate.
+  pub fn apply_shading_normal(&self, intersect_info: &mut IntersectionInfo) {
+    intersect_info.normal = self
+      .get_material()
+      .get_shading_normal(&intersect_info.coords, intersect_info.geometric_normal);
+  }
+
   pub fn get_material(&self) -> Arc<Material> {
-    self.shape.get_material()
+    spectral::get_material(self.shape.get_material())
   }
 
   pub fn get_id(&self) -> u32 {
     self.id
   }
 
+  pub fn get_area(&self) -> f64 {
+    self.shape.get_area()
+  }
+
+  pub fn sample_surface(&self, u1: f64, u2: f64) -> Option<SurfaceSample> {
+    self.shape.sample_surface(u1, u2)
+  }
+
+  // emissive shapes need a finite area to be sampled as lights
+  pub fn is_emitter(&self) -> bool {
+    self.get_material().has_emission() && self.get_area() > 0.0
+  }
+
+  // luminance of the emission times the area, the emission is taken at a few points as it can be
+  // textured.  like Light::get_power it's only used to decide which emitters to sample more.
+  pub fn get_power(&self) -> f64 {
+    let material = self.get_material();
+    let points = [(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)];
+    let mut luminance = 0.0;
+    for &(u1, u2) in &points {
+      if let Some(sample) = self.sample_surface(u1, u2) {
+        let c = material.get_emission(&sample.coords);
+        luminance += 0.2126 * c.r + 0.7152 * c.g + 0.0722 * c.b;
+      }
+    }
+    luminance / points.len() as f64 * self.get_area()
+  }
+
   pub fn get_bounding_box(&self) -> Arc<Box<BoundingBox>> {
     self.bbox.clone()
   }
@@ -72,11 +112,31 @@ impl CompiledLight {
 }
 
 impl CompiledLight {
-  pub fn get_position(&self) -> PosVector {
-    self.light.get_position()
+  // the light's color is upsampled in the spectral mode, like materials are by CompiledShape
+  pub fn illuminate(&self, point: PosVector) -> LightSample {
+    let mut sample = self.light.illuminate(point);
+    sample.color = spectral::upsample(sample.color);
+    sample
+  }
+  pub fn sample(&self, point: PosVector, u1: f64, u2: f64) -> LightSample {
+    let mut sample = self.light.sample(point, u1, u2);
+    sample.color = spectral::upsample(sample.color);
+    sample
+  }
+  pub fn is_area(&self) -> bool {
+    self.light.is_area()
+  }
+  pub fn get_power(&self) -> f64 {
+    self.light.get_power()
+  }
+  pub fn get_bounds(&self) -> Option<(PosVector, PosVector)> {
+    self.light.get_bounds()
+  }
+  pub fn get_emission_cone(&self) -> (PosVector, f64) {
+    self.light.get_emission_cone()
   }
   pub fn get_color(&self) -> ColorVector {
-    self.light.get_color()
+    spectral::upsample(self.light.get_color())
   }
 }
 
@@ -85,6 +145,10 @@ pub struct Scene {
   pub background: Background,
   pub shapes: HashMap<u32, Box<CompiledShape>>,
   pub lights: HashMap<u32, Box<CompiledLight>>,
+  pub light_sampler: LightSampler, // picks from the lights when there are too many to shade with all, and from the emitters
+  pub emitters: Vec<u32>, // ids of the shapes with emissive materials
+  pub medium: Option<HomogeneousMedium>, // fills the space outside of all shapes, e.g. fog
+  pub environment: Option<Arc<dyn Environment>>, // replaces the background color and lights the scene
 }
 
 unsafe impl Send for Scene {}
@@ -94,16 +158,20 @@ impl Scene {
   pub fn new(background: Background, shapes: Vec<Box<Shape>>, lights: Vec<Box<Light>>) -> Scene {
     let mut compiled_shapes: HashMap<u32, Box<CompiledShape>> = HashMap::new();
     let mut compiled_lights: HashMap<u32, Box<CompiledLight>> = HashMap::new();
+    let mut emitters: Vec<u32> = Vec::new();
+    let mut emitter_powers: Vec<(u32, f64)> = Vec::new();
 
     let (elapsed, _) = measure_time(|| {
       let mut current_shape_id: u32 = 1;
 
 
       for shape in shapes {
-        compiled_shapes.insert(current_shape_id, Box::new(CompiledShape::new(
-          Arc::new(shape),
-          current_shape_id,
-        )));
+        let compiled_shape = CompiledShape::new(Arc::new(shape), current_shape_id);
+        if compiled_shape.is_emitter() {
+          emitters.push(current_shape_id);
+          emitter_powers.push((current_shape_id, compiled_shape.get_power()));
+        }
+        compiled_shapes.insert(current_shape_id, Box::new(compiled_shape));
         current_shape_id = current_shape_id + 1;
       }
 
@@ -122,7 +190,11 @@ impl Scene {
     Scene {
       background,
       shapes: compiled_shapes,
+      light_sampler: LightSampler::new(&compiled_lights, &emitter_powers),
       lights: compiled_lights,
+      emitters,
+      medium: None,
+      environment: None,
     }
   }
 
@@ -290,23 +362,13 @@ pub fn new_marbles_scene(
     id = id + 1;
   }
 
-  let chess_mat = ChessboardMaterial {
-    color_even: ColorVector::new(0.8, 0.8, 0.8),
-    color_odd: ColorVector::new(0.0, 0.0, 0.0),
-    material: BaseMaterial {
-      reflection: 0.2,
-      transparency: 0.0,
-      gloss: 1.0,
-      refraction: 0.0,
-    },
-    scale: 15.0,
-  };
+  let materials = MaterialLibrary::new_with_presets();
 
   if show_plane {
     shapes.push(Box::new(PlaneShape {
       position: plane_pos,
       d_val: plane_d_val,
-      material: Arc::new(chess_mat),
+      material: materials.get("checker").unwrap(),
       id,
     }));
 
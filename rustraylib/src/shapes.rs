@@ -2,9 +2,10 @@ use std::fmt;
 use std::f64;
 use std::sync::Arc;
 
-use material::Material;
+use material::{Material, SurfaceCoords};
 use posvector::PosVector;
 use camera::Ray;
+use sampling;
 use tracer::IntersectionInfo;
 
 #[derive(Debug)]
@@ -264,11 +265,29 @@ impl BoundingBox {
   }
 }
 
-pub trait Shape: fmt::Debug {
+// A point picked on the surface of a shape, e.g. on an emissive shape that is sampled as an area light.
+#[derive(Debug, Copy, Clone)]
+pub struct SurfaceSample {
+  pub normal: PosVector,
+  pub coords: SurfaceCoords,
+}
+
+// shapes, their materials and those materials' textures are shared by the render threads
+pub trait Shape: fmt::Debug + Send + Sync {
   fn get_position(&self) -> PosVector;
   fn intersect(&self, ray: &Ray) -> IntersectionInfo;
   fn get_material(&self) -> Arc<Material>;
   fn calculate_bounding_planes(&self, unit_vec: PosVector) -> Bound;
+
+  // surface area, 0.0 for shapes that can't be sampled (e.g. infinite planes)
+  fn get_area(&self) -> f64 {
+    0.0
+  }
+
+  // a point uniformly distributed over the surface for u1, u2 in [0,1)
+  fn sample_surface(&self, _u1: f64, _u2: f64) -> Option<SurfaceSample> {
+    None
+  }
 }
 
 #[derive(Debug, Clone)]
@@ -280,6 +299,11 @@ pub struct TriangleShape {
   pub front_material: Arc<Material>,
   pub back_material: Arc<Material>,
   pub id: u32,
+  // object space is relative to this point.  triangles of the same mesh share it so that object
+  // space textures run across their edges, it defaults to the world origin.
+  pub origin: PosVector,
+  // texture coordinates of va, vb and vc, see set_uvs
+  uvs: [(f64, f64); 3],
 
   edge_ab: PosVector,
   edge_bc: PosVector,
@@ -289,6 +313,8 @@ pub struct TriangleShape {
   plane_coefficient: f64,
   u_beta: PosVector,
   u_gamma: PosVector,
+  dpdu: PosVector,
+  dpdv: PosVector,
 }
 
 impl TriangleShape {
@@ -339,6 +365,8 @@ impl TriangleShape {
       front_material,
       back_material,
       id,
+      origin: PosVector::new_default(),
+      uvs: [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)],
       edge_ab,
       edge_bc,
       edge_ca,
@@ -347,9 +375,49 @@ impl TriangleShape {
       plane_coefficient,
       u_beta,
       u_gamma,
+      dpdu: edge_ab,
+      dpdv: edge_ca.multiply_by_scalar(-1.0),
+    }
+  }
+
+  // per vertex texture coordinates.  without them the uv is the barycentric position in the
+  // triangle, so image and normal maps would start over on every triangle of a mesh.
+  pub fn set_uvs(&mut self, uv_a: (f64, f64), uv_b: (f64, f64), uv_c: (f64, f64)) {
+    self.uvs = [uv_a, uv_b, uv_c];
+
+    // solve edge_ab = du1 * dpdu + dv1 * dpdv and vc - va = du2 * dpdu + dv2 * dpdv
+    let (du1, dv1) = (uv_b.0 - uv_a.0, uv_b.1 - uv_a.1);
+    let (du2, dv2) = (uv_c.0 - uv_a.0, uv_c.1 - uv_a.1);
+    let edge_ac = self.edge_ca.multiply_by_scalar(-1.0);
+    let determinant = du1 * dv2 - dv1 * du2;
+    if determinant.abs() > 1e-12 {
+      self.dpdu = self
+        .edge_ab
+        .multiply_by_scalar(dv2)
+        .subtract(edge_ac.multiply_by_scalar(dv1))
+        .divide_by_scalar(determinant);
+      self.dpdv = edge_ac
+        .multiply_by_scalar(du1)
+        .subtract(self.edge_ab.multiply_by_scalar(du2))
+        .divide_by_scalar(determinant);
+    } else {
+      // degenerate mapping, there's no tangent frame to bump along
+      self.dpdu = PosVector::new_default();
+      self.dpdv = PosVector::new_default();
     }
   }
 
+  // beta and gamma are the barycentric weights of vb and vc
+  fn get_surface_coords(&self, beta: f64, gamma: f64, position: PosVector) -> SurfaceCoords {
+    let alpha = 1.0 - beta - gamma;
+    let u = alpha * self.uvs[0].0 + beta * self.uvs[1].0 + gamma * self.uvs[2].0;
+    let v = alpha * self.uvs[0].1 + beta * self.uvs[1].1 + gamma * self.uvs[2].1;
+    let mut coords = SurfaceCoords::new(u, v, position, position.subtract(self.origin));
+    coords.tangent = self.dpdu;
+    coords.bitangent = self.dpdv;
+    coords
+  }
+
   pub fn is_well_formed(&self) -> bool {
     self.normal.magnitude_squared() > 0.0
   }
@@ -402,17 +470,19 @@ impl Shape for TriangleShape {
     }
 
     let _returned_pos = q; // point of intersection
+    // q = va + v_coord * (vb - va) + w_coord * (vc - va)
+    let coords = self.get_surface_coords(v_coord, w_coord, q);
     let color = if front_face {
-      self.front_material.get_color(v_coord, w_coord)
+      self.front_material.get_color(&coords)
     } else {
-      self.back_material.get_color(v_coord, w_coord)
+      self.back_material.get_color(&coords)
     };
 
     if no_intersection {
       IntersectionInfo::new_default()
     } else {
       // found intersection
-      IntersectionInfo::new(color,intersect_distance,self.normal, q)
+      IntersectionInfo::new(color, intersect_distance, self.normal, coords)
     }
   }
 
@@ -443,6 +513,23 @@ impl Shape for TriangleShape {
 
     Bound::new(min_d, max_d)
   }
+
+  fn get_area(&self) -> f64 {
+    self.magnitude / 2.0
+  }
+
+  fn sample_surface(&self, u1: f64, u2: f64) -> Option<SurfaceSample> {
+    let (u, v) = sampling::uniform_sample_triangle(u1, u2);
+    let offset = self
+      .edge_ab
+      .multiply_by_scalar(u)
+      .subtract(self.edge_ca.multiply_by_scalar(v));
+    let coords = self.get_surface_coords(u, v, self.va.add(offset));
+    Some(SurfaceSample {
+      normal: self.normal,
+      coords,
+    })
+  }
 }
 
 #[derive(Debug, Clone)]
@@ -453,6 +540,27 @@ pub struct SphereShape {
   pub id: u32,
 }
 
+impl SphereShape {
+  fn get_surface_coords(&self, position: PosVector, normal: PosVector) -> SurfaceCoords {
+    let mut coords = SurfaceCoords::new(0.0, 0.0, position, position.subtract(self.position));
+    if self.material.has_texture() {
+      // spherical mapping around the z (up) axis
+      coords.u = 0.5 + normal.y.atan2(normal.x) / (2.0 * f64::consts::PI);
+      coords.v = 0.5 + normal.z.clamp(-1.0, 1.0).asin() / f64::consts::PI;
+
+      // derivatives of the mapping, degenerate at the poles
+      let d = coords.object_position;
+      let rho = (d.x * d.x + d.y * d.y).sqrt();
+      if rho > 0.0 {
+        coords.tangent = PosVector::new(-d.y, d.x, 0.0).multiply_by_scalar(2.0 * f64::consts::PI);
+        coords.bitangent = PosVector::new(-d.z * d.x / rho, -d.z * d.y / rho, rho)
+          .multiply_by_scalar(f64::consts::PI);
+      }
+    }
+    coords
+  }
+}
+
 impl Shape for SphereShape {
   fn get_position(&self) -> PosVector {
     self.position
@@ -465,19 +573,23 @@ impl Shape for SphereShape {
     let d = b * b - c;
 
     if d > 0.0 {
-      let distance = -b - d.sqrt();
+      // use the far root when the ray starts inside the sphere (e.g. refracted rays)
+      let mut distance = -b - d.sqrt();
+      if distance < 0.0 {
+        distance = -b + d.sqrt();
+      }
       let position = ray
         .get_position()
         .add(ray.get_direction().multiply_by_scalar(distance));
       let normal = position.subtract(self.position).normalize();
 
-      // todo: u/v coordinate texture mapping if self.material has a texture
-      let color = self.material.get_color(0.0, 0.0);
+      let coords = self.get_surface_coords(position, normal);
+      let color = self.material.get_color(&coords);
 
       // println!("intersected sphere!");
 
       // found intersection
-      IntersectionInfo::new(color,distance,normal, position)
+      IntersectionInfo::new(color, distance, normal, coords)
     } else {
       IntersectionInfo::new_default()
     }
@@ -491,6 +603,19 @@ impl Shape for SphereShape {
     let cd = unit_vec.dot_product(self.position);
     Bound::new(cd + self.radius, cd - self.radius)
   }
+
+  fn get_area(&self) -> f64 {
+    4.0 * f64::consts::PI * self.radius * self.radius
+  }
+
+  fn sample_surface(&self, u1: f64, u2: f64) -> Option<SurfaceSample> {
+    let normal = sampling::uniform_sample_sphere(u1, u2);
+    let position = self.position.add(normal.multiply_by_scalar(self.radius));
+    Some(SurfaceSample {
+      normal,
+      coords: self.get_surface_coords(position, normal),
+    })
+  }
 }
 
 #[derive(Debug, Clone)]
@@ -520,17 +645,23 @@ impl Shape for PlaneShape {
           .get_position()
           .add(ray.get_direction().multiply_by_scalar(t));
 
-        let mut color = self.material.get_color(0.0, 0.0);
+        // planes have no origin of their own so object space is world space
+        let mut coords = SurfaceCoords::new(0.0, 0.0, intersect_position, intersect_position);
         if self.material.has_texture() {
           let vec_u = PosVector::new(self.position.y, self.position.z, -self.position.x);
           let vec_v = vec_u.cross(self.position);
-          let u = intersect_position.dot_product(vec_u);
-          let v = intersect_position.dot_product(vec_v);
-          color = self.material.get_color(u, v);
+          coords.u = intersect_position.dot_product(vec_u);
+          coords.v = intersect_position.dot_product(vec_v);
+
+          // vec_u isn't always in the plane, its in plane part moves along u only
+          let t = vec_u.subtract(self.position.multiply_by_scalar(self.position.dot_product(vec_u)));
+          coords.tangent = t.divide_by_scalar(t.magnitude_squared());
+          coords.bitangent = vec_v.divide_by_scalar(vec_v.magnitude_squared());
         }
+        let color = self.material.get_color(&coords);
 
         // println!("intersected plane!");
-        IntersectionInfo::new(color,t,self.position,intersect_position)
+        IntersectionInfo::new(color, t, self.position, coords)
       }
     }
   }
@@ -0,0 +1,201 @@
+use std::f64;
+
+use color::ColorVector;
+use environment::{Environment, EnvironmentMap, EnvironmentSample};
+use light::DirectionalLight;
+use posvector::PosVector;
+
+// resolution the sky is baked at to importance sample it
+const SAMPLING_WIDTH: u32 = 128;
+const SAMPLING_HEIGHT: u32 = 64;
+
+// coefficients of the perez sky luminance distribution, see PreethamSky
+#[derive(Debug, Copy, Clone)]
+struct Perez {
+  a: f64,
+  b: f64,
+  c: f64,
+  d: f64,
+  e: f64,
+}
+
+impl Perez {
+  // theta is the zenith angle of the view direction, gamma the angle between it and the sun
+  fn eval(&self, theta: f64, gamma: f64) -> f64 {
+    let cos_gamma = gamma.cos();
+    (1.0 + self.a * (self.b / theta.cos().max(0.01)).exp())
+      * (1.0 + self.c * (self.d * gamma).exp() + self.e * cos_gamma * cos_gamma)
+  }
+}
+
+// Daylight sky after Preetham, Shirley and Smits, "A Practical Analytic Model for Daylight" (1999).
+// Turbidity is the haziness of the air: 2 is a very clear sky, 3 a clear one and 10 is hazy.
+// The sun itself isn't part of the sky, add the light from get_sun to the scene next to it.
+#[derive(Debug, Clone)]
+pub struct PreethamSky {
+  sun_direction: PosVector,
+  turbidity: f64,
+  zenith: (f64, f64, f64), // chromaticity x, y and luminance Y straight up
+  perez_x: Perez,
+  perez_y: Perez,
+  perez_luminance: Perez,
+  sampling: EnvironmentMap,
+  pub intensity: f64,     // scales the luminance, which the model gives in kcd/m^2, to the usual 0..1 colors
+  pub ground_albedo: f64, // below the horizon is a gray ground reflecting the sky
+}
+
+impl PreethamSky {
+  // sun position in degrees, elevation above the horizon and azimuth from +x towards +y
+  pub fn new(sun_elevation: f64, sun_azimuth: f64, turbidity: f64) -> PreethamSky {
+    let elevation = sun_elevation.clamp(0.0, 90.0).to_radians();
+    let azimuth = sun_azimuth.to_radians();
+    let sun_direction = PosVector::new(
+      elevation.cos() * azimuth.cos(),
+      elevation.cos() * azimuth.sin(),
+      elevation.sin(),
+    );
+    let t = turbidity.max(1.0);
+    let theta_s = f64::consts::FRAC_PI_2 - elevation;
+
+    let chi = (4.0 / 9.0 - t / 120.0) * (f64::consts::PI - 2.0 * theta_s);
+    let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+    let chromaticity = |m: [[f64; 4]; 3]| {
+      let angles = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+      let turbidities = [t * t, t, 1.0];
+      let mut value = 0.0;
+      for i in 0..3 {
+        for j in 0..4 {
+          value += turbidities[i] * m[i][j] * angles[j];
+        }
+      }
+      value
+    };
+    let zenith_x = chromaticity([
+      [0.00166, -0.00375, 0.00209, 0.0],
+      [-0.02903, 0.06377, -0.03202, 0.00394],
+      [0.11693, -0.21196, 0.06052, 0.25886],
+    ]);
+    let zenith_y = chromaticity([
+      [0.00275, -0.00610, 0.00317, 0.0],
+      [-0.04214, 0.08970, -0.04153, 0.00516],
+      [0.15346, -0.26756, 0.06670, 0.26688],
+    ]);
+
+    let mut sky = PreethamSky {
+      sun_direction,
+      turbidity: t,
+      zenith: (zenith_x, zenith_y, zenith_luminance),
+      perez_x: Perez {
+        a: -0.0193 * t - 0.2592,
+        b: -0.0665 * t + 0.0008,
+        c: -0.0004 * t + 0.2125,
+        d: -0.0641 * t - 0.8989,
+        e: -0.0033 * t + 0.0452,
+      },
+      perez_y: Perez {
+        a: -0.0167 * t - 0.2608,
+        b: -0.0950 * t + 0.0092,
+        c: -0.0079 * t + 0.2102,
+        d: -0.0441 * t - 1.6537,
+        e: -0.0109 * t + 0.0529,
+      },
+      perez_luminance: Perez {
+        a: 0.1787 * t - 1.4630,
+        b: -0.3554 * t + 0.4275,
+        c: -0.0227 * t + 5.3251,
+        d: 0.1206 * t - 2.5771,
+        e: -0.0670 * t + 0.3703,
+      },
+      // placeholder until the sky can be baked
+      sampling: EnvironmentMap::new(1, 1, vec![ColorVector::new(1.0, 1.0, 1.0)]),
+      intensity: 0.08,
+      ground_albedo: 0.3,
+    };
+    sky.sampling = sky.bake();
+    sky
+  }
+
+  // the sky as an environment map, which is then sampled in proportion to its brightness
+  fn bake(&self) -> EnvironmentMap {
+    let mut pixels = Vec::with_capacity((SAMPLING_WIDTH * SAMPLING_HEIGHT) as usize);
+    for y in 0..SAMPLING_HEIGHT {
+      for x in 0..SAMPLING_WIDTH {
+        let direction = self.sampling.get_direction(
+          (x as f64 + 0.5) / SAMPLING_WIDTH as f64,
+          (y as f64 + 0.5) / SAMPLING_HEIGHT as f64,
+        );
+        pixels.push(self.get_radiance(direction));
+      }
+    }
+    EnvironmentMap::new(SAMPLING_WIDTH, SAMPLING_HEIGHT, pixels)
+  }
+
+  pub fn get_sun_direction(&self) -> PosVector {
+    self.sun_direction
+  }
+
+  // color of the sunlight after it passed through the atmosphere, with rayleigh and aerosol
+  // extinction from the same paper.  white light straight from above on a clear day is about 1.
+  pub fn get_sun_color(&self) -> ColorVector {
+    let theta_s = self.sun_direction.z.clamp(0.0, 1.0).acos();
+    let degrees = theta_s.to_degrees();
+    // relative optical mass of the air along the path of the light
+    let air_mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - degrees).max(0.01).powf(-1.253));
+    let beta = 0.04608 * self.turbidity - 0.04586;
+    let transmittance = |wavelength: f64| {
+      let rayleigh = (-0.008735 * wavelength.powf(-4.08) * air_mass).exp();
+      let aerosol = (-beta * wavelength.powf(-1.3) * air_mass).exp();
+      rayleigh * aerosol
+    };
+    // wavelengths in micrometers
+    ColorVector::new(transmittance(0.65), transmittance(0.55), transmittance(0.45))
+  }
+
+  // a light for the sun that goes with the sky
+  pub fn get_sun(&self) -> DirectionalLight {
+    DirectionalLight::new(self.sun_direction.multiply_by_scalar(-1.0), self.get_sun_color())
+  }
+
+  fn get_sky_radiance(&self, direction: PosVector) -> ColorVector {
+    let theta = direction.z.clamp(0.0, 1.0).acos();
+    let gamma = direction.dot_product(self.sun_direction).clamp(-1.0, 1.0).acos();
+    let theta_s = self.sun_direction.z.clamp(0.0, 1.0).acos();
+    let relative = |perez: &Perez| perez.eval(theta, gamma) / perez.eval(0.0, theta_s);
+
+    let (zenith_x, zenith_y, zenith_luminance) = self.zenith;
+    let x = zenith_x * relative(&self.perez_x);
+    let y = (zenith_y * relative(&self.perez_y)).max(1e-4);
+    let luminance = zenith_luminance * relative(&self.perez_luminance) * self.intensity;
+
+    // xyY to XYZ to linear srgb
+    let cie_x = x * luminance / y;
+    let cie_z = (1.0 - x - y) * luminance / y;
+    ColorVector::new(
+      (3.2406 * cie_x - 1.5372 * luminance - 0.4986 * cie_z).max(0.0),
+      (-0.9689 * cie_x + 1.8758 * luminance + 0.0415 * cie_z).max(0.0),
+      (0.0557 * cie_x - 0.2040 * luminance + 1.0570 * cie_z).max(0.0),
+    )
+  }
+}
+
+impl Environment for PreethamSky {
+  fn get_radiance(&self, direction: PosVector) -> ColorVector {
+    if direction.z >= 0.0 {
+      return self.get_sky_radiance(direction);
+    }
+    // the ground, as a dim mirror image of the sky
+    let mirrored = PosVector::new(direction.x, direction.y, -direction.z);
+    self.get_sky_radiance(mirrored).multiply_by_scalar(self.ground_albedo)
+  }
+
+  fn sample(&self, u1: f64, u2: f64) -> Option<EnvironmentSample> {
+    self.sampling.sample(u1, u2).map(|sample| EnvironmentSample {
+      radiance: self.get_radiance(sample.direction),
+      ..sample
+    })
+  }
+
+  fn pdf(&self, direction: PosVector) -> f64 {
+    self.sampling.pdf(direction)
+  }
+}
@@ -0,0 +1,438 @@
+use std::cell::Cell;
+use std::sync::Arc;
+
+use bsdf::{Bsdf, Lobe};
+use color::ColorVector;
+use material::{Material, SurfaceCoords};
+use medium::HomogeneousMedium;
+use posvector::PosVector;
+
+// visible range the spectral mode samples, in nanometers
+pub const MIN_WAVELENGTH: f64 = 380.0;
+pub const MAX_WAVELENGTH: f64 = 720.0;
+
+// The wavelength the current thread is tracing at, None when rendering in rgb.
+// Set per camera ray like the random state in sampling, as is whether the ray's path went through
+// a dispersive material.
+thread_local! {
+  static WAVELENGTH: Cell<Option<f64>> = const { Cell::new(None) };
+  static DISPERSED: Cell<bool> = const { Cell::new(false) };
+  static WHITE_NORMALIZATION: Cell<Option<ColorVector>> = const { Cell::new(None) };
+}
+
+pub fn set_wavelength(wavelength: Option<f64>) {
+  WAVELENGTH.with(|w| w.set(wavelength));
+}
+
+pub fn get_wavelength() -> Option<f64> {
+  WAVELENGTH.with(|w| w.get())
+}
+
+// returns whether a path refracted through a dispersive material since the last call
+pub fn take_dispersed() -> bool {
+  DISPERSED.with(|d| d.replace(false))
+}
+
+// index of refraction for the path being traced, at its wavelength in the spectral mode.
+// refracting through a dispersive material is noted for take_dispersed.
+pub fn get_refraction(material: &dyn Material, coords: &SurfaceCoords) -> f64 {
+  if material.is_dispersive() {
+    DISPERSED.with(|d| d.set(true));
+  }
+  match get_wavelength() {
+    Some(wavelength) => material.get_refraction_at(coords, wavelength),
+    None => material.get_refraction(coords),
+  }
+}
+
+// the color at the wavelength being traced, which is the value of its upsampled spectrum in every
+// channel.  in rgb the color stays as it is.
+pub fn upsample(color: ColorVector) -> ColorVector {
+  match get_wavelength() {
+    Some(wavelength) => {
+      let value = rgb_to_spectrum(color, wavelength);
+      ColorVector::new(value, value, value)
+    }
+    None => color,
+  }
+}
+
+pub fn upsample_medium(medium: Option<HomogeneousMedium>) -> Option<HomogeneousMedium> {
+  match get_wavelength() {
+    Some(_) => medium.map(|medium| HomogeneousMedium {
+      absorption: upsample(medium.absorption),
+      scattering: upsample(medium.scattering),
+      g: medium.g,
+    }),
+    None => medium,
+  }
+}
+
+fn upsample_bsdf(bsdf: Bsdf) -> Bsdf {
+  let lobes = bsdf
+    .lobes
+    .iter()
+    .map(|lobe| match *lobe {
+      Lobe::Lambert { albedo } => Lobe::Lambert {
+        albedo: upsample(albedo),
+      },
+      Lobe::Coated { albedo, f0 } => Lobe::Coated {
+        albedo: upsample(albedo),
+        f0: upsample(f0),
+      },
+      Lobe::Ggx { f0, alpha, weight } => Lobe::Ggx {
+        f0: upsample(f0),
+        alpha,
+        weight,
+      },
+    })
+    .collect();
+  Bsdf::new(lobes)
+}
+
+// A material seen at the wavelength being traced: its reflectances, emission and medium are upsampled
+// where the path meets it, so that each interaction filters the light the way its spectrum would.
+#[derive(Debug)]
+struct SpectralMaterial {
+  material: Arc<dyn Material>,
+}
+
+impl Material for SpectralMaterial {
+  fn get_color(&self, coords: &SurfaceCoords) -> ColorVector {
+    upsample(self.material.get_color(coords))
+  }
+  fn has_texture(&self) -> bool {
+    self.material.has_texture()
+  }
+  fn get_gloss(&self, coords: &SurfaceCoords) -> f64 {
+    self.material.get_gloss(coords)
+  }
+  fn get_reflection(&self, coords: &SurfaceCoords) -> f64 {
+    self.material.get_reflection(coords)
+  }
+  fn get_refraction(&self, coords: &SurfaceCoords) -> f64 {
+    self.material.get_refraction(coords)
+  }
+  fn get_transparency(&self, coords: &SurfaceCoords) -> f64 {
+    self.material.get_transparency(coords)
+  }
+  fn get_diffuse(&self, coords: &SurfaceCoords) -> f64 {
+    self.material.get_diffuse(coords)
+  }
+  fn get_specular(&self, coords: &SurfaceCoords) -> f64 {
+    self.material.get_specular(coords)
+  }
+  fn get_shininess(&self, coords: &SurfaceCoords) -> f64 {
+    self.material.get_shininess(coords)
+  }
+  fn get_shading_normal(&self, coords: &SurfaceCoords, normal: PosVector) -> PosVector {
+    self.material.get_shading_normal(coords, normal)
+  }
+  fn get_bsdf(&self, coords: &SurfaceCoords) -> Option<Bsdf> {
+    self.material.get_bsdf(coords).map(upsample_bsdf)
+  }
+  fn get_emission(&self, coords: &SurfaceCoords) -> ColorVector {
+    upsample(self.material.get_emission(coords))
+  }
+  fn has_emission(&self) -> bool {
+    self.material.has_emission()
+  }
+  fn get_refraction_at(&self, coords: &SurfaceCoords, wavelength: f64) -> f64 {
+    self.material.get_refraction_at(coords, wavelength)
+  }
+  fn is_dispersive(&self) -> bool {
+    self.material.is_dispersive()
+  }
+  fn get_medium(&self) -> Option<HomogeneousMedium> {
+    upsample_medium(self.material.get_medium())
+  }
+}
+
+// the material as the path being traced sees it, upsampled in the spectral mode
+pub fn get_material(material: Arc<dyn Material>) -> Arc<dyn Material> {
+  match get_wavelength() {
+    Some(_) => Arc::new(SpectralMaterial { material }),
+    None => material,
+  }
+}
+
+// maps u in [0,1) uniformly onto the visible range
+pub fn sample_wavelength(u: f64) -> f64 {
+  MIN_WAVELENGTH + u * (MAX_WAVELENGTH - MIN_WAVELENGTH)
+}
+
+// piecewise gaussian used by the cie fit below
+fn lobe(x: f64, mu: f64, sigma_low: f64, sigma_high: f64) -> f64 {
+  let t = (x - mu) / if x < mu { sigma_low } else { sigma_high };
+  (-0.5 * t * t).exp()
+}
+
+// cie 1931 color matching functions, as fitted by Wyman, Sloan and Shirley (2013)
+pub fn get_cie_xyz(wavelength: f64) -> (f64, f64, f64) {
+  let x = 1.056 * lobe(wavelength, 599.8, 37.9, 31.0) + 0.362 * lobe(wavelength, 442.0, 16.0, 26.7)
+    - 0.065 * lobe(wavelength, 501.1, 20.4, 26.2);
+  let y = 0.821 * lobe(wavelength, 568.8, 46.9, 40.5) + 0.286 * lobe(wavelength, 530.9, 16.3, 31.1);
+  let z = 1.217 * lobe(wavelength, 437.0, 11.8, 36.0) + 0.681 * lobe(wavelength, 459.0, 26.0, 13.8);
+  (x, y, z)
+}
+
+// linear srgb response to a unit of light at the wavelength.  can be negative for colors outside of srgb.
+pub fn get_rgb_response(wavelength: f64) -> ColorVector {
+  let (x, y, z) = get_cie_xyz(wavelength);
+  ColorVector::new(
+    3.2406 * x - 1.5372 * y - 0.4986 * z,
+    -0.9689 * x + 1.8758 * y + 0.0415 * z,
+    0.0557 * x - 0.2040 * y + 1.0570 * z,
+  )
+}
+
+// response to a constant spectrum of 1 over the sampled range, which is what rgb white upsamples to
+pub fn get_white_normalization() -> ColorVector {
+  WHITE_NORMALIZATION.with(|cached| {
+    if let Some(white) = cached.get() {
+      return white;
+    }
+    let mut white = ColorVector::new(0.0, 0.0, 0.0);
+    let mut wavelength = MIN_WAVELENGTH + 0.5;
+    while wavelength < MAX_WAVELENGTH {
+      white = white.add(get_rgb_response(wavelength));
+      wavelength += 1.0;
+    }
+    cached.set(Some(white));
+    white
+  })
+}
+
+// Smits' basis spectra for upsampling rgb, 10 bins over 380..720nm
+const SMITS_WHITE: [f64; 10] = [1.0, 1.0, 0.9999, 0.9993, 0.9992, 0.9998, 1.0, 1.0, 1.0, 1.0];
+const SMITS_CYAN: [f64; 10] = [0.971, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0, 0.0, 0.0];
+const SMITS_MAGENTA: [f64; 10] = [1.0, 1.0, 0.9685, 0.2229, 0.0, 0.0458, 0.8369, 1.0, 1.0, 0.9959];
+const SMITS_YELLOW: [f64; 10] = [0.0001, 0.0, 0.1088, 0.6651, 1.0, 1.0, 0.9996, 0.9586, 0.9685, 0.984];
+const SMITS_RED: [f64; 10] = [0.1012, 0.0515, 0.0, 0.0, 0.0, 0.0, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f64; 10] = [0.0, 0.0, 0.0273, 0.7937, 1.0, 0.9418, 0.1719, 0.0, 0.0, 0.0025];
+const SMITS_BLUE: [f64; 10] = [1.0, 1.0, 0.8916, 0.3323, 0.0, 0.0, 0.0003, 0.0369, 0.0483, 0.0496];
+
+// linear interpolation between the bin centers
+fn smits_basis(spectrum: &[f64; 10], wavelength: f64) -> f64 {
+  let bin_width = (MAX_WAVELENGTH - MIN_WAVELENGTH) / 10.0;
+  let x = ((wavelength - MIN_WAVELENGTH) / bin_width - 0.5).clamp(0.0, 9.0);
+  let i = (x.floor() as usize).min(8);
+  let t = x - i as f64;
+  spectrum[i] * (1.0 - t) + spectrum[i + 1] * t
+}
+
+// value at the wavelength of a smooth spectrum with (roughly) the given rgb color, after Smits (1999)
+pub fn rgb_to_spectrum(color: ColorVector, wavelength: f64) -> f64 {
+  let basis = |spectrum: &[f64; 10]| smits_basis(spectrum, wavelength);
+  let (r, g, b) = (color.r, color.g, color.b);
+
+  if r <= g && r <= b {
+    r * basis(&SMITS_WHITE) + if g <= b {
+      (g - r) * basis(&SMITS_CYAN) + (b - g) * basis(&SMITS_BLUE)
+    } else {
+      (b - r) * basis(&SMITS_CYAN) + (g - b) * basis(&SMITS_GREEN)
+    }
+  } else if g <= r && g <= b {
+    g * basis(&SMITS_WHITE) + if r <= b {
+      (r - g) * basis(&SMITS_MAGENTA) + (b - r) * basis(&SMITS_BLUE)
+    } else {
+      (b - g) * basis(&SMITS_MAGENTA) + (r - b) * basis(&SMITS_RED)
+    }
+  } else {
+    b * basis(&SMITS_WHITE) + if r <= g {
+      (r - b) * basis(&SMITS_YELLOW) + (g - r) * basis(&SMITS_GREEN)
+    } else {
+      (g - b) * basis(&SMITS_YELLOW) + (r - g) * basis(&SMITS_RED)
+    }
+  }
+}
+
+// Index of refraction as a function of wavelength.  Both formulas take the wavelength in micrometers.
+#[derive(Debug, Copy, Clone)]
+pub enum Dispersion {
+  // n = a + b / l^2
+  Cauchy { a: f64, b: f64 },
+  // n^2 = 1 + sum of b_i l^2 / (l^2 - c_i)
+  Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+  pub fn new_cauchy(a: f64, b: f64) -> Dispersion {
+    Dispersion::Cauchy { a, b }
+  }
+
+  // cauchy fit to the index at the sodium d line and the abbe number, as found in glass catalogs.
+  // lower abbe numbers disperse more.
+  pub fn from_abbe(ior: f64, abbe: f64) -> Dispersion {
+    let (d, f, c) = (0.5876, 0.4861, 0.6563);
+    let b = (ior - 1.0) / (abbe * (1.0 / (f * f) - 1.0 / (c * c)));
+    Dispersion::Cauchy { a: ior - b / (d * d), b }
+  }
+
+  // schott n-bk7 crown glass
+  pub fn new_bk7() -> Dispersion {
+    Dispersion::Sellmeier {
+      b: [1.03961212, 0.231792344, 1.01046945],
+      c: [0.00600069867, 0.0200179144, 103.560653],
+    }
+  }
+
+  pub fn new_fused_silica() -> Dispersion {
+    Dispersion::Sellmeier {
+      b: [0.6961663, 0.4079426, 0.8974794],
+      c: [0.0046791, 0.0135121, 97.934003],
+    }
+  }
+
+  pub fn new_diamond() -> Dispersion {
+    Dispersion::Sellmeier {
+      b: [0.3306, 4.3356, 0.0],
+      c: [0.030625, 0.011236, 0.0],
+    }
+  }
+
+  pub fn get_ior(&self, wavelength: f64) -> f64 {
+    let l = wavelength / 1000.0;
+    let l2 = l * l;
+    match *self {
+      Dispersion::Cauchy { a, b } => a + b / l2,
+      Dispersion::Sellmeier { b, c } => {
+        let mut n2 = 1.0;
+        for i in 0..3 {
+          n2 += b[i] * l2 / (l2 - c[i]);
+        }
+        n2.max(1.0).sqrt()
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use camera::Camera;
+  use light::{Light, PointLight};
+  use material::{PbrMaterial, PhongMaterial};
+  use renderer::RenderData;
+  use scene::{Background, Scene};
+  use shapes::{PlaneShape, Shape};
+  use tracer::RayTracer;
+
+  // integrates what get_value returns at each wavelength back to rgb
+  fn integrate<F: Fn() -> ColorVector>(get_value: F) -> ColorVector {
+    let mut rgb = ColorVector::new(0.0, 0.0, 0.0);
+    let mut wavelength = MIN_WAVELENGTH + 0.5;
+    while wavelength < MAX_WAVELENGTH {
+      set_wavelength(Some(wavelength));
+      rgb = rgb.add(get_rgb_response(wavelength).multiply_by_scalar(get_value().g));
+      wavelength += 1.0;
+    }
+    set_wavelength(None);
+    let white = get_white_normalization();
+    ColorVector::new(rgb.r / white.r, rgb.g / white.g, rgb.b / white.b)
+  }
+
+  // what a color comes back as after upsampling it to a spectrum and integrating that to rgb
+  fn round_trip(color: ColorVector) -> ColorVector {
+    integrate(|| upsample(color))
+  }
+
+  fn assert_close(a: ColorVector, b: ColorVector, tolerance: f64) {
+    let diff = (a.r - b.r).abs().max((a.g - b.g).abs()).max((a.b - b.b).abs());
+    assert!(diff < tolerance, "{:?} != {:?}", a, b);
+  }
+
+  fn get_coords() -> SurfaceCoords {
+    SurfaceCoords::new(0.0, 0.0, PosVector::new_default(), PosVector::new_default())
+  }
+
+  #[test]
+  fn white_round_trips() {
+    for &level in &[1.0, 0.5, 0.1] {
+      let rgb = round_trip(ColorVector::new(level, level, level));
+      for &c in &[rgb.r, rgb.g, rgb.b] {
+        assert!((c - level).abs() < 0.01 * level, "{} comes back as {:?}", level, rgb);
+      }
+    }
+  }
+
+  #[test]
+  fn colored_material_round_trips() {
+    let color = ColorVector::new(0.8, 0.3, 0.1);
+    let material: Arc<dyn Material> = Arc::new(PhongMaterial::new(color, 1.0, 0.0, 1.0, 0.0, 1.0));
+    let coords = get_coords();
+    let rgb = integrate(|| get_material(material.clone()).get_color(&coords));
+    assert_close(rgb, color, 0.02);
+    // and the diffuse lobe of a bsdf
+    let pbr: Arc<dyn Material> = Arc::new(PbrMaterial::new(color, 0.0, 0.5));
+    let rgb = integrate(|| {
+      let bsdf = get_material(pbr.clone()).get_bsdf(&coords).unwrap();
+      match bsdf.lobes[0] {
+        Lobe::Coated { albedo, .. } => albedo,
+        _ => panic!("{:?} has no diffuse lobe", bsdf),
+      }
+    });
+    assert_close(rgb, color, 0.02);
+    // outside of the spectral mode the material is left alone
+    assert_close(get_material(material).get_color(&coords), color, 1e-12);
+  }
+
+  // a colored plane seen through a pane of glass, in spectral mode when the glass disperses.
+  // the pane's index is 1 at every wavelength, so all wavelengths take the same path.
+  fn render_through_glass(dispersive: bool) -> ColorVector {
+    let white = ColorVector::new(1.0, 1.0, 1.0);
+    let mut glass = PhongMaterial::new(white, 0.0, 0.0, 1.0, 1.0, 1.0);
+    if dispersive {
+      glass.dispersion = Some(Dispersion::new_cauchy(1.0, 0.0));
+    }
+    let pane: Box<dyn Shape> = Box::new(PlaneShape {
+      position: PosVector::new(0.0, 0.0, 1.0),
+      d_val: -1.0,
+      material: Arc::new(glass) as Arc<dyn Material>,
+      id: 1,
+    });
+    let color = ColorVector::new(0.8, 0.3, 0.1);
+    let floor: Box<dyn Shape> = Box::new(PlaneShape {
+      position: PosVector::new(0.0, 0.0, 1.0),
+      d_val: 0.0,
+      material: Arc::new(PhongMaterial::new(color, 1.0, 0.0, 1.0, 0.0, 1.0)) as Arc<dyn Material>,
+      id: 2,
+    });
+    let light: Box<dyn Light> = Box::new(PointLight::new(PosVector::new(1.0, 2.0, 5.0), white));
+    let background = Background::new(ColorVector::new(0.0, 0.0, 0.0), 0.0);
+    let scene = Scene::new(background, vec![pane, floor], vec![light]);
+    let camera = Camera::new(
+      PosVector::new(0.0, -3.0, 4.0),
+      PosVector::new(0.0, 0.0, 0.0),
+      PosVector::new(0.0, 0.0, 1.0),
+      45.0,
+    );
+    let mut render_data = RenderData::new(1, 1, 5, 1, false);
+    render_data.spectral = true;
+    render_data.wavelength_samples = 64;
+    let tracer = RayTracer::new(camera, render_data, Arc::new(scene));
+    tracer.get_pixel_color(0, 0)
+  }
+
+  #[test]
+  fn colored_material_round_trips_through_dispersion() {
+    let rgb = render_through_glass(false);
+    assert!(rgb.r > 0.1 && rgb.r > 2.0 * rgb.g && rgb.g > rgb.b, "{:?}", rgb);
+    let spectral = render_through_glass(true);
+    assert_close(spectral, rgb, 0.03 * rgb.r);
+  }
+
+  #[test]
+  fn dispersion_is_noted() {
+    let white = ColorVector::new(1.0, 1.0, 1.0);
+    let coords = get_coords();
+    let mut glass = PhongMaterial::new(white, 0.0, 0.5, 200.0, 1.0, 1.5);
+    take_dispersed();
+    get_refraction(&glass, &coords);
+    assert!(!take_dispersed());
+    glass.dispersion = Some(Dispersion::new_bk7());
+    get_refraction(&glass, &coords);
+    assert!(take_dispersed());
+    assert!(!take_dispersed());
+  }
+}
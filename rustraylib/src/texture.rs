@@ -0,0 +1,132 @@
+use std::fmt;
+use std::sync::Arc;
+
+use color::ColorVector;
+use material::SurfaceCoords;
+
+// A value that varies over a surface.  Any material parameter can be driven by a texture,
+// scalar parameters (reflection, transparency, ...) use the average of the three channels.
+pub trait Texture: fmt::Debug + Send + Sync {
+  fn get_value(&self, coords: &SurfaceCoords) -> ColorVector;
+
+  fn get_scalar(&self, coords: &SurfaceCoords) -> f64 {
+    let c = self.get_value(coords);
+    (c.r + c.g + c.b) / 3.0
+  }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ConstantTexture {
+  pub color: ColorVector,
+}
+
+impl ConstantTexture {
+  pub fn new(color: ColorVector) -> ConstantTexture {
+    ConstantTexture { color }
+  }
+
+  pub fn new_scalar(val: f64) -> ConstantTexture {
+    ConstantTexture::new(ColorVector::new(val, val, val))
+  }
+}
+
+impl Texture for ConstantTexture {
+  fn get_value(&self, _coords: &SurfaceCoords) -> ColorVector {
+    self.color
+  }
+
+  fn get_scalar(&self, _coords: &SurfaceCoords) -> f64 {
+    (self.color.r + self.color.g + self.color.b) / 3.0
+  }
+}
+
+// 2D checker pattern in (u, v)
+#[derive(Debug, Copy, Clone)]
+pub struct CheckerTexture {
+  pub color_even: ColorVector,
+  pub color_odd: ColorVector,
+  pub scale: f64,
+}
+
+impl CheckerTexture {
+  pub fn new(color_even: ColorVector, color_odd: ColorVector, scale: f64) -> CheckerTexture {
+    CheckerTexture {
+      color_even,
+      color_odd,
+      scale,
+    }
+  }
+
+  /// <summary>
+  /// wraps any value up in the inteval [-scale/2,scale/2] in a rotational manner
+  /// e.g. 1.7 -> -0.3 for a scale of 2
+  /// </summary>
+  fn wrap_up_scale(t: f64, scale: f64) -> f64 {
+    let mut x = t % scale;
+    if x < -scale / 2.0 {
+      x += scale;
+    }
+    if x >= scale / 2.0 {
+      x -= scale;
+    }
+    x
+  }
+}
+
+impl Texture for CheckerTexture {
+  fn get_value(&self, coords: &SurfaceCoords) -> ColorVector {
+    let t = CheckerTexture::wrap_up_scale(coords.u, self.scale)
+      * CheckerTexture::wrap_up_scale(coords.v, self.scale);
+    if t < 0.0 {
+      self.color_even
+    } else {
+      self.color_odd
+    }
+  }
+}
+
+// blends from a to b by the mask (0.0 is all a, 1.0 is all b)
+#[derive(Debug, Clone)]
+pub struct MixTexture {
+  pub a: Arc<dyn Texture>,
+  pub b: Arc<dyn Texture>,
+  pub mask: Arc<dyn Texture>,
+}
+
+impl MixTexture {
+  pub fn new(a: Arc<dyn Texture>, b: Arc<dyn Texture>, mask: Arc<dyn Texture>) -> MixTexture {
+    MixTexture { a, b, mask }
+  }
+}
+
+impl Texture for MixTexture {
+  fn get_value(&self, coords: &SurfaceCoords) -> ColorVector {
+    let weight = self.mask.get_scalar(coords);
+    self
+      .a
+      .get_value(coords)
+      .blend(self.b.get_value(coords), weight)
+  }
+}
+
+// product of two textures, e.g. to tint or darken another texture
+#[derive(Debug, Clone)]
+pub struct ScaleTexture {
+  pub texture: Arc<dyn Texture>,
+  pub scale: Arc<dyn Texture>,
+}
+
+impl ScaleTexture {
+  pub fn new(texture: Arc<dyn Texture>, scale: Arc<dyn Texture>) -> ScaleTexture {
+    ScaleTexture { texture, scale }
+  }
+}
+
+impl Texture for ScaleTexture {
+  fn get_value(&self, coords: &SurfaceCoords) -> ColorVector {
+    self
+      .texture
+      .get_value(coords)
+      .multiply(self.scale.get_value(coords))
+  }
+}
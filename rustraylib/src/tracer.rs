@@ -1,9 +1,22 @@
+use std::f64;
 use std::sync::Arc;
 use color::ColorVector;
 use posvector::PosVector;
 use camera::{Camera, Ray};
-use renderer::RenderData;
-use scene::{Scene,CompiledShape,CompiledLight};
+use material::SurfaceCoords;
+use medium::HomogeneousMedium;
+use renderer::{RenderData, RenderMode};
+use integrator::{new_integrator, Integrator};
+use sampling;
+use spectral;
+use scene::{Scene,CompiledLight};
+use lightsampling::LightSampling;
+
+// distance secondary rays are moved off of the surface they start on to avoid self intersection
+const RAY_EPSILON: f64 = 0.0001;
+
+// transparent surfaces a shadow ray passes through before it's considered blocked
+const MAX_TRANSMITTANCE_CROSSINGS: u32 = 16;
 
 #[derive(Debug)]
 pub struct IntersectionInfo {
@@ -11,8 +24,10 @@ pub struct IntersectionInfo {
   pub distance: f64,
   pub element_id: u32,
   pub is_hit: bool,
-  pub normal: PosVector,
+  pub normal: PosVector, // shading normal
+  pub geometric_normal: PosVector,
   pub position: PosVector,
+  pub coords: SurfaceCoords,
 }
 
 impl IntersectionInfo {
@@ -23,22 +38,36 @@ impl IntersectionInfo {
       element_id: 0,
       is_hit: false,
       normal: PosVector::new_default(),
+      geometric_normal: PosVector::new_default(),
       position: PosVector::new_default(),
+      coords: SurfaceCoords::new(0.0, 0.0, PosVector::new_default(), PosVector::new_default()),
     }
   }
 
-  pub fn new(color: ColorVector, distance: f64, normal: PosVector, position: PosVector) -> IntersectionInfo {
+  pub fn new(color: ColorVector, distance: f64, normal: PosVector, coords: SurfaceCoords) -> IntersectionInfo {
     IntersectionInfo {
       color,
       distance,
       element_id: 0,
       is_hit: true,
       normal,
-      position
+      geometric_normal: normal,
+      position: coords.position,
+      coords,
     }    
   }
 }
 
+// the hue of a transparent surface at full brightness, what it tints the light passing through it with
+fn get_filter_color(color: ColorVector) -> ColorVector {
+  let brightest = color.r.max(color.g).max(color.b);
+  if brightest <= 0.0 {
+    ColorVector::new(1.0, 1.0, 1.0)
+  } else {
+    color.multiply_by_scalar(1.0 / brightest)
+  }
+}
+
 #[derive(Debug)]
 pub struct RayTraceStatistics {
   pub num_rays_traced: u64,
@@ -61,33 +90,98 @@ pub struct RayTracer {
   pub scene: Arc<Scene>,
   pub use_kd_tree: bool,
   pub stats: Arc<RayTraceStatistics>,
+  pub integrator: Arc<dyn Integrator>,
 }
 
 impl RayTracer {
+  // renders with the integrator for render_data.render_mode
   pub fn new(camera: Camera, render_data: RenderData, scene: Arc<Scene>) -> RayTracer {
+    let integrator = new_integrator(render_data.render_mode);
+    RayTracer::new_with_integrator(camera, render_data, scene, integrator)
+  }
+
+  pub fn new_with_integrator(
+    camera: Camera,
+    render_data: RenderData,
+    scene: Arc<Scene>,
+    integrator: Arc<dyn Integrator>,
+  ) -> RayTracer {
     RayTracer {
       camera,
       render_data,
       scene,
       use_kd_tree: false,
       stats: Arc::new(RayTraceStatistics::new()),
+      integrator,
     }
   }
 
-  fn get_reflection_ray(&self, p: PosVector, n: PosVector, v: PosVector) -> Ray {
+  pub fn get_reflection_ray(&self, p: PosVector, n: PosVector, v: PosVector) -> Ray {
     let c1 = -(n.dot_product(v));
     let rl = v.add(n.multiply_by_scalar(2.0).multiply_by_scalar(c1));
     Ray::new(p, rl)
   }
 
-  fn get_refraction_ray(&self, p: PosVector, n: PosVector, v: PosVector, refraction: f64) -> Ray {
-    let c1 = n.dot_product(v);
-    let c2 = 1.0 - refraction * refraction * (1.0 - c1 * c1).sqrt();
-    let t = n.multiply_by_scalar(refraction * c1 - c2)
-      .subtract(v.multiply_by_scalar(refraction))
-      .multiply_by_scalar(-1.0)
-      .normalize();
-    Ray::new(p, t)
+  // snell's law.  n must face against v and eta is the ratio of the indices of refraction (from / to).
+  // returns None on total internal reflection.
+  pub fn get_refraction_direction(&self, n: PosVector, v: PosVector, eta: f64) -> Option<PosVector> {
+    let cos_i = -(n.dot_product(v));
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t > 1.0 {
+      None
+    } else {
+      let cos_t = (1.0 - sin2_t).sqrt();
+      Some(
+        v.multiply_by_scalar(eta)
+          .add(n.multiply_by_scalar(eta * cos_i - cos_t))
+          .normalize(),
+      )
+    }
+  }
+
+  // schlick's approximation of the fresnel reflectance going from index n1 into index n2.
+  pub fn get_fresnel_reflectance(&self, cos_i: f64, n1: f64, n2: f64) -> f64 {
+    if n1 == n2 {
+      // index matched boundary, e.g. around smoke
+      return 0.0;
+    }
+    let mut cos = cos_i;
+    if n1 > n2 {
+      // leaving the denser medium, the transmitted angle is the one that matters
+      let eta = n1 / n2;
+      let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+      if sin2_t > 1.0 {
+        return 1.0;
+      }
+      cos = (1.0 - sin2_t).sqrt();
+    }
+    let r0 = ((n1 - n2) / (n1 + n2)).powi(2);
+    r0 + (1.0 - r0) * (1.0 - cos).powi(5)
+  }
+
+  // rays that are allowed to hit the surface they leave from (e.g. inside a sphere) are nudged off
+  // of it instead of excluding the shape entirely.
+  pub fn offset_ray(&self, p: PosVector, n: PosVector, dir: PosVector) -> Ray {
+    let offset = if dir.dot_product(n) > 0.0 {
+      n.multiply_by_scalar(RAY_EPSILON)
+    } else {
+      n.multiply_by_scalar(-RAY_EPSILON)
+    };
+    Ray::new(p.add(offset), dir)
+  }
+
+  // what rays that miss everything see
+  pub fn get_background_color(&self, direction: PosVector) -> ColorVector {
+    let color = match self.scene.environment {
+      Some(ref environment) => environment.get_radiance(direction.normalize()),
+      None => self.scene.background.color,
+    };
+    spectral::upsample(color)
+  }
+
+  // the medium outside of all shapes, upsampled in the spectral mode like the materials' media
+  pub fn get_scene_medium(&self) -> Option<HomogeneousMedium> {
+    spectral::upsample_medium(self.scene.medium)
   }
 
   fn test_intersection_basic(&self, ray: &Ray, exclude_id: u32) -> IntersectionInfo {
@@ -102,6 +196,7 @@ impl RayTracer {
       }
     }
 
+    best_info.color = spectral::upsample(best_info.color);
     best_info
   }
 
@@ -109,7 +204,7 @@ impl RayTracer {
     IntersectionInfo::new_default()
   }
 
-  fn test_intersection(&self, ray: &Ray, exclude_id: u32) -> IntersectionInfo {
+  pub fn test_intersection(&self, ray: &Ray, exclude_id: u32) -> IntersectionInfo {
     // self.stats.add_ray_traced();
     if self.use_kd_tree {
       self.test_intersection_kd(ray, exclude_id)
@@ -118,225 +213,332 @@ impl RayTracer {
     }
   }
 
-  fn render_diffuse(
+  // closest hit of a ray whose hit gets shaded, with the bump or normal map applied to its normal.
+  // shadow and occlusion rays only need the geometry and use test_intersection.
+  pub fn test_shading_intersection(&self, ray: &Ray, exclude_id: u32) -> IntersectionInfo {
+    let mut info = self.test_intersection(ray, exclude_id);
+    if info.is_hit {
+      if let Some(elem) = self.scene.get_shape(&info.element_id) {
+        elem.apply_shading_normal(&mut info);
+      }
+    }
+    info
+  }
+
+  // fraction of the light that makes it along the ray over max_distance, through media and
+  // transparent surfaces (ignoring their refraction).  medium is the one the ray starts in.
+  // each surface lets through its transparency, tinted with the hue of its color.
+  pub fn get_light_transmittance(
     &self,
-    current_color: ColorVector,
-    intersection_info: &IntersectionInfo,
-    light: &Box<CompiledLight>,
+    ray: &Ray,
+    max_distance: f64,
+    medium: Option<HomogeneousMedium>,
   ) -> ColorVector {
-    let mut color = current_color;
-    if self.render_data.render_diffuse {
-      let v = light
-        .get_position()
-        .subtract(intersection_info.position)
-        .normalize();
-
-      let l = v.dot_product(intersection_info.normal);
-      if l > 0.0 {
-        color = color.add(
-          intersection_info
-            .color
-            .multiply(light.get_color())
-            .multiply_by_scalar(l),
-        )
+    let direction = ray.get_direction();
+    let mut transmittance = ColorVector::new(1.0, 1.0, 1.0);
+    let mut medium = medium;
+    let mut origin = ray.get_position();
+    let mut remaining = max_distance;
+
+    for _ in 0..MAX_TRANSMITTANCE_CROSSINGS {
+      let info = self.test_intersection_basic(&Ray::new(origin, direction), 0);
+      let is_hit = info.is_hit && info.distance < remaining - RAY_EPSILON;
+      let length = if is_hit { info.distance } else { remaining };
+      if let Some(ref medium) = medium {
+        transmittance = transmittance.multiply(medium.get_transmittance(length));
       }
+      if !is_hit {
+        return transmittance;
+      }
+
+      let material = match self.scene.get_shape(&info.element_id) {
+        Some(elem) => elem.get_material(),
+        None => return transmittance,
+      };
+      let transparency = material.get_transparency(&info.coords);
+      if transparency <= 0.0 {
+        return ColorVector::new(0.0, 0.0, 0.0);
+      }
+      transmittance = transmittance.multiply(get_filter_color(info.color).multiply_by_scalar(transparency));
+
+      // crossing into or out of the shape
+      medium = if direction.dot_product(info.geometric_normal) < 0.0 {
+        material.get_medium()
+      } else {
+        self.get_scene_medium()
+      };
+      origin = info.position.add(direction.multiply_by_scalar(RAY_EPSILON));
+      remaining -= info.distance + RAY_EPSILON;
     }
-    color
+
+    ColorVector::new(0.0, 0.0, 0.0)
   }
 
-  fn render_reflection(
+  // the medium a shadow ray from the shading point starts in.  medium is the one the ray that hit the
+  // point travelled through, light from the other side of a transparent surface travels through
+  // what's behind it.
+  fn get_shadow_medium(
     &self,
-    current_color: ColorVector,
     intersection_info: &IntersectionInfo,
     ray: &Ray,
-    depth: u32,
-  ) -> ColorVector {
-    let mut color = current_color;
-
-    if self.render_data.render_reflection {
-      match self.scene.get_shape(&intersection_info.element_id) {
-        None => {}
-        Some(elem) => {
-          if elem.get_material().get_reflection() > 0.0 {
-            let reflection_ray = self.get_reflection_ray(
-              intersection_info.position,
-              intersection_info.normal,
-              ray.get_direction(),
-            );
-            let mut refl = self.test_intersection(&reflection_ray, elem.get_id());
-            if refl.is_hit && refl.distance > 0.0 {
-              refl.color = self.ray_trace(&refl, &reflection_ray, depth + 1);
-            } else {
-              refl.color = self.scene.background.color;
-            }
-
-            color = color.blend(refl.color, elem.get_material().get_reflection());
-          }
-        }
+    direction: PosVector,
+    medium: Option<HomogeneousMedium>,
+  ) -> Option<HomogeneousMedium> {
+    let n = intersection_info.geometric_normal;
+    if n.dot_product(direction) * n.dot_product(ray.get_direction()) > 0.0 {
+      if direction.dot_product(n) < 0.0 {
+        self
+          .scene
+          .get_shape(&intersection_info.element_id)
+          .and_then(|elem| elem.get_material().get_medium())
+      } else {
+        self.get_scene_medium()
       }
+    } else {
+      medium
     }
+  }
 
-    color
+  // what is left of a light by the time it reaches the shading point, with a shadow ray that continues
+  // through transparent surfaces.  medium is the one the ray that hit the point travelled through.
+  pub fn get_shadow_transmittance(
+    &self,
+    intersection_info: &IntersectionInfo,
+    ray: &Ray,
+    direction: PosVector,
+    distance: f64,
+    medium: Option<HomogeneousMedium>,
+  ) -> ColorVector {
+    let shadow_ray = self.offset_ray(intersection_info.position, intersection_info.geometric_normal, direction);
+    let medium = self.get_shadow_medium(intersection_info, ray, direction, medium);
+    self.get_light_transmittance(&shadow_ray, distance, medium)
   }
 
-  fn render_refraction(
+  // like get_shadow_transmittance without a shadow ray: surfaces in between are ignored, only the
+  // medium around the shading point (e.g. the scene's fog) attenuates the light.
+  pub fn get_unshadowed_transmittance(
     &self,
-    current_color: ColorVector,
     intersection_info: &IntersectionInfo,
     ray: &Ray,
-    depth: u32,
+    direction: PosVector,
+    distance: f64,
+    medium: Option<HomogeneousMedium>,
   ) -> ColorVector {
-    let mut color = current_color;
-
-    if self.render_data.render_refraction {
-      match self.scene.get_shape(&intersection_info.element_id) {
-        None => {}
-        Some(elem) => {
-          if elem.get_material().get_transparency() > 0.0 {
-            let refraction_ray = self.get_refraction_ray(
-              intersection_info.position,
-              intersection_info.normal,
-              ray.get_direction(),
-              elem.clone().get_material().get_refraction(),
-            );
-            let mut refr = elem.clone().intersect(&refraction_ray);
-            if refr.is_hit {
-              match self.scene.get_shape(&refr.element_id) {
-                None => {}
-                Some(refrelem) => {
-                  let element_refraction_ray = self.get_refraction_ray(
-                    refr.position,
-                    refr.normal,
-                    refraction_ray.get_direction(),
-                    refrelem.get_material().get_refraction(),
-                  );
-                  refr = self.test_intersection(&element_refraction_ray, elem.get_id());
-                  if refr.is_hit && refr.distance > 0.0 {
-                    refr.color = self.ray_trace(&refr, &element_refraction_ray, depth + 1);
-                  } else {
-                    refr.color = self.scene.background.color;
-                  }
-                }
-              }
-            } else {
-              refr.color = self.scene.background.color;
-            }
-            color = color.blend(refr.color, elem.get_material().get_transparency());
-          }
-        }
-      }
+    match self.get_shadow_medium(intersection_info, ray, direction, medium) {
+      Some(ref medium) => medium.get_transmittance(distance),
+      None => ColorVector::new(1.0, 1.0, 1.0),
     }
-    color
   }
 
-  fn render_highlights(
+  // light scattered towards the viewer by the medium at point p, from a ray travelling along direction
+  fn get_inscattered_light(
     &self,
-    current_color: ColorVector,
-    elem: &CompiledShape,
-    shadow_intersection: &IntersectionInfo,
-    light: &Box<CompiledLight>,
+    p: PosVector,
+    direction: PosVector,
+    medium: &HomogeneousMedium,
   ) -> ColorVector {
-    let mut color = current_color;
-    if self.render_data.render_highlights && !shadow_intersection.is_hit
-      && elem.get_material().get_gloss() > 0.0
-    {
-      let lv = elem
-        .get_position()
-        .subtract(light.get_position())
-        .normalize();
-      let e = self
-        .camera
-        .get_position()
-        .subtract(elem.get_position())
-        .normalize();
-      let _h = e.subtract(lv).normalize();
-      let gloss_weight = 0.0; // todo: pow(std::max(dot(info->Normal(), h), 0.0), shininess);
-      color = color.add(light.get_color().multiply_by_scalar(gloss_weight));
+    let mut color = ColorVector::new(0.0, 0.0, 0.0);
+
+    for light in self.scene.lights.values() {
+      let sample = light.sample(p, sampling::next_f64(), sampling::next_f64());
+      if sample.distance <= 0.0 {
+        continue;
+      }
+      let wi = sample.direction;
+      let transmittance = self.get_light_transmittance(&Ray::new(p, wi), sample.distance, Some(*medium));
+      // lights are scaled by pi like on surfaces, see the light units in light.rs
+      let phase = medium.phase(wi.dot_product(direction)) * f64::consts::PI;
+      color = color.add(sample.color.multiply(transmittance).multiply_by_scalar(phase));
+    }
+
+    // a single emitter, picked in proportion to its power
+    if let Some((emitter_id, pick_pdf, u1)) = self.scene.light_sampler.sample_emitter(sampling::next_f64()) {
+      let sample = self
+        .scene
+        .get_shape(&emitter_id)
+        .and_then(|emitter| emitter.sample_surface(u1, sampling::next_f64()).map(|sample| (emitter, sample)));
+      if let Some((emitter, sample)) = sample {
+        let to_light = sample.coords.position.subtract(p);
+        let distance = to_light.magnitude();
+        if distance > 0.0 {
+          let wi = to_light.divide_by_scalar(distance);
+          let cos_light = sample.normal.dot_product(wi).abs();
+          let transmittance = self.get_light_transmittance(&Ray::new(p, wi), distance, Some(*medium));
+          let weight = medium.phase(wi.dot_product(direction)) * cos_light * emitter.get_area()
+            / (distance * distance * pick_pdf);
+          color = color.add(
+            emitter
+              .get_material()
+              .get_emission(&sample.coords)
+              .multiply(transmittance)
+              .multiply_by_scalar(weight),
+          );
+        }
+      }
     }
+
     color
   }
 
-  fn render_shadow_and_highlights(
+  // attenuates the color seen at distance along the ray by the medium in between, and adds the light
+  // the medium scatters towards the viewer (single scattering, estimated at num_samples sampled
+  // distances, none leaves the scattered light out).
+  pub fn render_medium(
     &self,
     current_color: ColorVector,
-    intersection_info: &IntersectionInfo,
-    light: &Box<CompiledLight>,
+    ray: &Ray,
+    distance: f64,
+    medium: &HomogeneousMedium,
+    num_samples: u32,
   ) -> ColorVector {
-    let mut color = current_color;
-
-    let v = light
-      .get_position()
-      .subtract(intersection_info.position)
-      .normalize();
-
-    let shadow_ray = Ray::new(intersection_info.position, v);
-
-    match self.scene.get_shape(&intersection_info.element_id) {
-      None => {}
-      Some(elem) => {
-        let shadow_intersection = self.test_intersection(&shadow_ray, elem.get_id());
-        if self.render_data.render_shadow {
-          if shadow_intersection.is_hit {
-            match self.scene.get_shape(&shadow_intersection.element_id) {
-              None => {}
-              Some(shadowelem) => {
-                if shadowelem.clone().get_id() != elem.get_id() {
-                  let trans = shadowelem.clone().get_material().get_transparency();
-                  let trans_power = trans.powf(0.5);
-                  color = color.multiply_by_scalar(0.5 + (0.5 * trans_power)); // todo: make sure this is ordered correctly for the power calculation
-                }
-              }
-            }
-          }
+    let mut color = current_color.multiply(medium.get_transmittance(distance));
+
+    if num_samples > 0 && medium.is_scattering() {
+      let mut scattered = ColorVector::new(0.0, 0.0, 0.0);
+      for _ in 0..num_samples {
+        if let Some((t, pdf)) = medium.sample_distance(sampling::next_f64(), distance) {
+          let p = ray
+            .get_position()
+            .add(ray.get_direction().multiply_by_scalar(t));
+          let light = self.get_inscattered_light(p, ray.get_direction(), medium);
+          scattered = scattered.add(
+            light
+              .multiply(medium.scattering)
+              .multiply(medium.get_transmittance(t))
+              .multiply_by_scalar(1.0 / pdf),
+          );
         }
-
-        color = self.render_highlights(color, elem.clone(), &shadow_intersection, light);
       }
+      color = color.add(scattered.multiply_by_scalar(1.0 / num_samples as f64));
     }
+
     color
   }
 
-  fn ray_trace(&self, intersection_info: &IntersectionInfo, ray: &Ray, depth: u32) -> ColorVector {
-    let mut color = intersection_info
-      .color
-      .multiply_by_scalar(self.scene.background.ambience);
-    // let shininess = 10.pow(intersection_info.element.material.gloss + 1.0);
-
-    // foreach light
-    // rust note:  need the & since we want references to the items in the iteration.
-    // by default, for loops use into_iter<> which is a MOVE
-    // http://hermanradtke.com/2015/06/22/effectively-using-iterators-in-rust.html
-    for (_, light) in &self.scene.lights {
-      color = self.render_diffuse(color, intersection_info, light);
-
-      // max depth of raytracing.  increasing depth calculates more color, but takes exp longer
-      if depth < self.render_data.ray_trace_depth {
-        color = self.render_reflection(color, intersection_info, ray, depth);
-        color = self.render_refraction(color, intersection_info, ray, depth);
-        color = self.render_shadow_and_highlights(color, intersection_info, light);
-      }
+  // the lights that shade point p on a surface facing n, each with the weight of its light.  that is
+  // every light at full weight, or light_samples lights picked at random with light_sampling, each
+  // weighted by how likely it was to be picked.
+  pub fn pick_lights(&self, p: PosVector, n: PosVector) -> Vec<(&CompiledLight, f64)> {
+    let num_light_samples = self.render_data.light_samples.max(1);
+    if self.render_data.light_sampling == LightSampling::All
+      || self.scene.light_sampler.get_light_count() <= num_light_samples as usize
+    {
+      return self.scene.lights.values().map(|light| (&**light, 1.0)).collect();
     }
 
-    color
+    let mut lights = Vec::new();
+    for i in 0..num_light_samples {
+      let u = (i as f64 + sampling::next_f64()) / num_light_samples as f64;
+      let picked = self
+        .scene
+        .light_sampler
+        .sample(self.render_data.light_sampling, p, n, u);
+      if let Some((light_id, pdf)) = picked {
+        if let Some(light) = self.scene.get_light(&light_id) {
+          lights.push((&**light, 1.0 / (num_light_samples as f64 * pdf)));
+        }
+      }
+    }
+    lights
   }
 
-  fn calculate_color(&self, ray: &Ray) -> ColorVector {
-    let intersection_info = self.test_intersection(ray, 0);
-    if intersection_info.is_hit {
-      self.ray_trace(&intersection_info, ray, 0)
+  // how open the hemisphere above the point is up to ao_radius, from 0 (enclosed) to 1 (nothing
+  // nearby).  the directions are cosine weighted, so what's in front of the surface counts the most.
+  pub fn get_ambient_occlusion(&self, intersection_info: &IntersectionInfo, ray: &Ray) -> f64 {
+    // the side of the surface the ray arrived at
+    let n = if intersection_info.normal.dot_product(ray.get_direction()) > 0.0 {
+      intersection_info.normal.multiply_by_scalar(-1.0)
     } else {
-      self.scene.background.color
+      intersection_info.normal
+    };
+
+    let num_samples = self.render_data.ao_samples.max(1);
+    let mut num_open = 0;
+    for i in 0..num_samples {
+      let (u1, u2) = sampling::stratified_sample(i, num_samples);
+      let wi = sampling::to_world(sampling::cosine_sample_hemisphere(u1, u2), n);
+      let ao_ray = self.offset_ray(intersection_info.position, intersection_info.geometric_normal, wi);
+      let info = self.test_intersection_basic(&ao_ray, 0);
+      if !info.is_hit || info.distance >= self.render_data.ao_radius {
+        num_open += 1;
+      }
     }
+    num_open as f64 / num_samples as f64
   }
 
-  pub fn get_pixel_color(&self, x: u32, y: u32) -> ColorVector {
-    // xp, yp are scaled as -1.0..1.0 each to represent their view range in the image regardless of final resolution.
-    let xp = x as f64 / self.render_data.width as f64 * 2.0 - 1.0;
-    let yp = -(y as f64 / self.render_data.height as f64 * 2.0 - 1.0); // yp is UP but our pixels are increasing in value DOWN.  so need inversion here.
+  // traces the ray once per sampled wavelength and converts the results back to rgb.
+  // while a wavelength is set, the colors of materials, lights and the background are upsampled
+  // where the path meets them (see spectral::upsample), so the radiance comes back at that wavelength.
+  // the ray is traced in rgb first, and that is the answer when its path met no dispersion.  the
+  // wavelengths replay its random numbers, so they follow the same path up to the dispersive surface.
+  fn calculate_spectral_color(&self, ray: &Ray) -> ColorVector {
+    let rng_state = sampling::get_state();
+    spectral::take_dispersed();
+    let rgb = self.integrator.get_radiance(self, ray);
+    if !spectral::take_dispersed() {
+      return rgb;
+    }
 
-    // println!("{},{} -> {},{}", x, y, xp, yp);
+    let num_samples = self.render_data.wavelength_samples.max(1);
+    // stratified over the visible range
+    let wavelengths: Vec<f64> = (0..num_samples)
+      .map(|i| spectral::sample_wavelength((i as f64 + sampling::next_f64()) / num_samples as f64))
+      .collect();
+    // the paths below go over the same numbers again, later rays continue on a fresh stream
+    let next_seed = (sampling::next_f64() * (1u64 << 53) as f64) as u64;
+
+    let mut color = ColorVector::new(0.0, 0.0, 0.0);
+    for wavelength in wavelengths {
+      spectral::set_wavelength(Some(wavelength));
+      sampling::set_state(rng_state);
+      // all channels hold the radiance at the wavelength
+      let radiance = self.integrator.get_radiance(self, ray);
+      let value = (radiance.r + radiance.g + radiance.b) / 3.0;
+      color = color.add(spectral::get_rgb_response(wavelength).multiply_by_scalar(value));
+    }
+    spectral::set_wavelength(None);
+    sampling::seed(next_seed);
+
+    // scaled so that a constant spectrum comes out white
+    let white = spectral::get_white_normalization();
+    let scale = (spectral::MAX_WAVELENGTH - spectral::MIN_WAVELENGTH) / num_samples as f64;
+    ColorVector::new(
+      color.r * scale / white.r,
+      color.g * scale / white.g,
+      color.b * scale / white.b,
+    )
+  }
 
-    let ray = self.camera.get_ray(xp, yp);
-    self.calculate_color(&ray)
+  pub fn get_pixel_color(&self, x: u32, y: u32) -> ColorVector {
+    sampling::seed(((y as u64) << 32) | x as u64);
+
+    // a single ray goes through the corner of the pixel, more are spread over it
+    let num_samples = self.render_data.samples_per_pixel.max(1);
+    let mut color = ColorVector::new(0.0, 0.0, 0.0);
+    for i in 0..num_samples {
+      let (dx, dy) = if num_samples > 1 {
+        sampling::stratified_sample(i, num_samples)
+      } else {
+        (0.0, 0.0)
+      };
+
+      // xp, yp are scaled as -1.0..1.0 each to represent their view range in the image regardless of final resolution.
+      let xp = (x as f64 + dx) / self.render_data.width as f64 * 2.0 - 1.0;
+      let yp = -((y as f64 + dy) / self.render_data.height as f64 * 2.0 - 1.0); // yp is UP but our pixels are increasing in value DOWN.  so need inversion here.
+
+      // println!("{},{} -> {},{}", x, y, xp, yp);
+
+      let ray = self.camera.get_ray(xp, yp);
+      // ambient occlusion has no colors to disperse
+      let is_spectral =
+        self.render_data.spectral && self.render_data.render_mode != RenderMode::AmbientOcclusion;
+      let sample_color = if is_spectral {
+        self.calculate_spectral_color(&ray)
+      } else {
+        self.integrator.get_radiance(self, &ray)
+      };
+      color = color.add(sample_color);
+    }
+    color.multiply_by_scalar(1.0 / num_samples as f64)
   }
 }
@@ -0,0 +1,778 @@
+use std::f64;
+
+use bsdf::{Bsdf, Lobe};
+use camera::Ray;
+use color::ColorVector;
+use integrator::Integrator;
+use light::LightSample;
+use medium::HomogeneousMedium;
+use posvector::PosVector;
+use sampling;
+use scene::{CompiledLight, CompiledShape};
+use spectral;
+use tracer::{IntersectionInfo, RayTracer};
+
+// Whitted style ray tracing, what RenderMode::Shaded renders: every hit is lit directly by the lights
+// (with shadow rays), emissive shapes and the environment, and mirror reflection and refraction are
+// followed recursively up to ray_trace_depth.  As in the NFF spec the parts add up: a hit is
+// Kd * diffuse + Ks * (specular + reflected) + T * transmitted, and Kd + Ks + T needn't be 1.
+// The render_* switches turn its parts off, they're all on for RenderMode::Shaded.  Render with
+// Renderer::render_frame_with_integrator to change them.
+#[derive(Debug)]
+pub struct WhittedIntegrator {
+  pub render_diffuse: bool,
+  pub render_reflection: bool,
+  pub render_refraction: bool,
+  pub render_shadow: bool,
+  pub render_highlights: bool,
+}
+
+impl WhittedIntegrator {
+  pub fn new() -> WhittedIntegrator {
+    WhittedIntegrator {
+      render_diffuse: true,
+      render_reflection: true,
+      render_refraction: true,
+      render_shadow: true,
+      render_highlights: true,
+    }
+  }
+
+  // the color seen along a ray, including the medium it travels through.
+  // depth is that of the surface the ray hits.  the background is scaled by background_weight, which
+  // is below 1 where the environment is also reached by render_environment.
+  fn get_ray_color(
+    &self,
+    tracer: &RayTracer,
+    ray: &Ray,
+    exclude_id: u32,
+    depth: u32,
+    medium: Option<HomogeneousMedium>,
+    background_weight: f64,
+  ) -> ColorVector {
+    let info = tracer.test_shading_intersection(ray, exclude_id);
+    let is_hit = info.is_hit && info.distance > 0.0;
+    let color = if is_hit {
+      self.ray_trace(tracer, &info, ray, depth, medium)
+    } else {
+      tracer
+        .get_background_color(ray.get_direction())
+        .multiply_by_scalar(background_weight)
+    };
+
+    match medium {
+      Some(ref medium) => {
+        let distance = if is_hit { info.distance } else { f64::INFINITY };
+        // the fog lights up like the diffuse surfaces
+        let num_samples = if !self.render_diffuse {
+          0
+        } else if depth > 0 {
+          1
+        } else {
+          tracer.render_data.medium_samples.max(1)
+        };
+        tracer.render_medium(color, ray, distance, medium, num_samples)
+      }
+      None => color,
+    }
+  }
+
+  fn trace_ray(
+    &self,
+    tracer: &RayTracer,
+    ray: &Ray,
+    exclude_id: u32,
+    depth: u32,
+    medium: Option<HomogeneousMedium>,
+  ) -> ColorVector {
+    self.get_ray_color(tracer, ray, exclude_id, depth + 1, medium, 1.0)
+  }
+
+  // rays render_glossy_reflection follows for a lobe
+  fn get_glossy_samples(&self, tracer: &RayTracer, lobe: &Lobe, depth: u32) -> u32 {
+    if lobe.is_specular() || depth > 0 {
+      1
+    } else {
+      tracer.render_data.glossy_samples.max(1)
+    }
+  }
+
+  // shadow rays render_environment casts
+  fn get_environment_samples(&self, tracer: &RayTracer, depth: u32) -> u32 {
+    if depth > 0 {
+      1
+    } else {
+      tracer.render_data.environment_samples.max(1)
+    }
+  }
+
+  // what reaches the hit point of a light in direction at distance.  without a shadow ray (switched
+  // off, or at the deepest bounce) occluders are ignored but the medium still attenuates the light.
+  #[allow(clippy::too_many_arguments)]
+  fn get_light_transmittance(
+    &self,
+    tracer: &RayTracer,
+    intersection_info: &IntersectionInfo,
+    ray: &Ray,
+    direction: PosVector,
+    distance: f64,
+    medium: Option<HomogeneousMedium>,
+    cast_shadow: bool,
+  ) -> ColorVector {
+    if cast_shadow {
+      tracer.get_shadow_transmittance(intersection_info, ray, direction, distance, medium)
+    } else {
+      tracer.get_unshadowed_transmittance(intersection_info, ray, direction, distance, medium)
+    }
+  }
+
+  fn render_diffuse(
+    &self,
+    tracer: &RayTracer,
+    current_color: ColorVector,
+    intersection_info: &IntersectionInfo,
+    ray: &Ray,
+    light: &LightSample,
+    bsdf: Option<&Bsdf>,
+  ) -> ColorVector {
+    let mut color = current_color;
+    if self.render_diffuse {
+      let v = light.direction;
+
+      let l = v.dot_product(intersection_info.normal);
+      if let Some(bsdf) = bsdf {
+        // lights give irradiance of pi * color * cos so a white lambert surface matches the non
+        // physically based materials, see the light units in light.rs
+        let wo = ray.get_direction().multiply_by_scalar(-1.0);
+        let f = bsdf.eval(intersection_info.normal, wo, v);
+        color = color.add(
+          f.multiply(light.color)
+            .multiply_by_scalar(l.max(0.0) * f64::consts::PI),
+        );
+      } else if l > 0.0 {
+        if let Some(elem) = tracer.scene.get_shape(&intersection_info.element_id) {
+          color = color.add(
+            intersection_info
+              .color
+              .multiply(light.color)
+              .multiply_by_scalar(l * elem.get_material().get_diffuse(&intersection_info.coords)),
+          )
+        }
+      }
+    }
+    color
+  }
+
+  // direct light from emissive shapes, estimated with shadow rays to random points on emitters picked
+  // in proportion to their power (see LightSampler::sample_emitter).  only the diffuse part of the
+  // surface is lit here, glossy and mirror reflections of an emitter already pick up its emission
+  // through their secondary rays.
+  #[allow(clippy::too_many_arguments)]
+  fn render_emitters(
+    &self,
+    tracer: &RayTracer,
+    current_color: ColorVector,
+    intersection_info: &IntersectionInfo,
+    ray: &Ray,
+    depth: u32,
+    bsdf: Option<&Bsdf>,
+    medium: Option<HomogeneousMedium>,
+  ) -> ColorVector {
+    if !self.render_diffuse {
+      return current_color;
+    }
+    let elem = match tracer.scene.get_shape(&intersection_info.element_id) {
+      Some(elem) => elem,
+      None => return current_color,
+    };
+
+    let coords = &intersection_info.coords;
+    let n = intersection_info.normal;
+    let wo = ray.get_direction().multiply_by_scalar(-1.0);
+    let diffuse_bsdf = bsdf.map(|bsdf| Bsdf::new(bsdf.get_diffuse_lobes()));
+    // lambertian reflectance for the non physically based materials
+    let albedo = intersection_info
+      .color
+      .multiply_by_scalar(elem.get_material().get_diffuse(coords) / f64::consts::PI);
+    let num_samples = if depth > 0 {
+      1
+    } else {
+      tracer.render_data.area_light_samples.max(1)
+    };
+
+    let mut emitter_color = ColorVector::new(0.0, 0.0, 0.0);
+    for i in 0..num_samples {
+      let (u1, u2) = sampling::stratified_sample(i, num_samples);
+      // u1 picks the emitter first, then the point on it
+      let (emitter_id, pick_pdf, u1) = match tracer.scene.light_sampler.sample_emitter(u1) {
+        Some(picked) => picked,
+        None => return current_color,
+      };
+      if emitter_id == elem.get_id() {
+        continue;
+      }
+      let emitter = match tracer.scene.get_shape(&emitter_id) {
+        Some(emitter) => emitter,
+        None => continue,
+      };
+      let sample = match emitter.sample_surface(u1, u2) {
+        Some(sample) => sample,
+        None => continue,
+      };
+      let to_light = sample.coords.position.subtract(intersection_info.position);
+      let distance = to_light.magnitude();
+      if distance <= 0.0 {
+        continue;
+      }
+      let wi = to_light.divide_by_scalar(distance);
+      let cos_surface = n.dot_product(wi);
+      // emissive surfaces light both of their sides
+      let cos_light = sample.normal.dot_product(wi).abs();
+      if cos_surface <= 0.0 || cos_light <= 0.0 {
+        continue;
+      }
+      let transmittance = self.get_light_transmittance(
+        tracer,
+        intersection_info,
+        ray,
+        wi,
+        distance,
+        medium,
+        self.render_shadow,
+      );
+
+      let f = match diffuse_bsdf {
+        Some(ref diffuse_bsdf) => diffuse_bsdf.eval(n, wo, wi),
+        None => albedo,
+      };
+      // points are picked with a pdf of 1 / area, which converts to solid angle by distance^2 / cos_light
+      let weight = cos_surface * cos_light * emitter.get_area() / (distance * distance * pick_pdf);
+      emitter_color = emitter_color.add(
+        emitter
+          .get_material()
+          .get_emission(&sample.coords)
+          .multiply(f)
+          .multiply(transmittance)
+          .multiply_by_scalar(weight),
+      );
+    }
+
+    current_color.add(emitter_color.multiply_by_scalar(1.0 / num_samples as f64))
+  }
+
+  // direct light from the environment, estimated with shadow rays in directions picked in proportion
+  // to its brightness.  it lights the diffuse part of the surface and the rough glossy lobes, the
+  // latter combined with the rays of render_glossy_reflection by multiple importance sampling (small
+  // bright parts of the environment are found by these rays, broad ones by the glossy rays).
+  // mirror reflections only see the environment through their secondary rays.
+  #[allow(clippy::too_many_arguments)]
+  fn render_environment(
+    &self,
+    tracer: &RayTracer,
+    current_color: ColorVector,
+    intersection_info: &IntersectionInfo,
+    ray: &Ray,
+    depth: u32,
+    bsdf: Option<&Bsdf>,
+    medium: Option<HomogeneousMedium>,
+  ) -> ColorVector {
+    let environment = match tracer.scene.environment {
+      Some(ref environment) => environment,
+      None => return current_color,
+    };
+    let elem = match tracer.scene.get_shape(&intersection_info.element_id) {
+      Some(elem) => elem,
+      None => return current_color,
+    };
+
+    let n = intersection_info.normal;
+    let wo = ray.get_direction().multiply_by_scalar(-1.0);
+    let render_diffuse = self.render_diffuse;
+    let diffuse_bsdf = bsdf.map(|bsdf| Bsdf::new(bsdf.get_diffuse_lobes()));
+    // lambertian reflectance for the non physically based materials
+    let albedo = intersection_info.color.multiply_by_scalar(
+      elem.get_material().get_diffuse(&intersection_info.coords) / f64::consts::PI,
+    );
+    // the rough lobes, and whether render_glossy_reflection traces rays for them too
+    let glossy_lobes = match bsdf {
+      Some(bsdf) if self.render_reflection => bsdf
+        .get_specular_lobes()
+        .into_iter()
+        .filter(|lobe| !lobe.is_specular())
+        .collect(),
+      _ => Vec::new(),
+    };
+    let is_glossy_traced = depth < tracer.render_data.ray_trace_depth;
+    if !render_diffuse && glossy_lobes.is_empty() {
+      return current_color;
+    }
+    let num_samples = self.get_environment_samples(tracer, depth);
+
+    let mut environment_color = ColorVector::new(0.0, 0.0, 0.0);
+    for i in 0..num_samples {
+      let (u1, u2) = sampling::stratified_sample(i, num_samples);
+      let sample = match environment.sample(u1, u2) {
+        Some(sample) => sample,
+        None => continue,
+      };
+      let wi = sample.direction;
+      let cos_surface = n.dot_product(wi);
+      if cos_surface <= 0.0 {
+        continue;
+      }
+      let transmittance = self.get_light_transmittance(
+        tracer,
+        intersection_info,
+        ray,
+        wi,
+        f64::INFINITY,
+        medium,
+        self.render_shadow,
+      );
+      let mut f = match diffuse_bsdf {
+        _ if !render_diffuse => ColorVector::new(0.0, 0.0, 0.0),
+        Some(ref diffuse_bsdf) => diffuse_bsdf.eval(n, wo, wi),
+        None => albedo,
+      };
+      for lobe in &glossy_lobes {
+        let weight = if is_glossy_traced {
+          let glossy_samples = self.get_glossy_samples(tracer, lobe, depth);
+          sampling::power_heuristic(
+            num_samples as f64 * sample.pdf,
+            glossy_samples as f64 * lobe.pdf(n, wo, wi),
+          )
+        } else {
+          1.0
+        };
+        f = f.add(lobe.eval(n, wo, wi).multiply_by_scalar(weight));
+      }
+      environment_color = environment_color.add(
+        spectral::upsample(sample.radiance)
+          .multiply(f)
+          .multiply(transmittance)
+          .multiply_by_scalar(cos_surface / sample.pdf),
+      );
+    }
+    current_color.add(environment_color.multiply_by_scalar(1.0 / num_samples as f64))
+  }
+
+  // adds what a single light contributes to the color
+  #[allow(clippy::too_many_arguments)]
+  fn render_light(
+    &self,
+    tracer: &RayTracer,
+    current_color: ColorVector,
+    intersection_info: &IntersectionInfo,
+    ray: &Ray,
+    depth: u32,
+    light: &CompiledLight,
+    bsdf: Option<&Bsdf>,
+    medium: Option<HomogeneousMedium>,
+  ) -> ColorVector {
+    if light.is_area() {
+      return self.render_area_light(
+        tracer,
+        current_color,
+        intersection_info,
+        ray,
+        depth,
+        light,
+        bsdf,
+        medium,
+      );
+    }
+    let mut sample = light.illuminate(intersection_info.position);
+
+    // max depth of raytracing.  increasing depth calculates more color, but takes exp longer
+    let is_deepest = depth >= tracer.render_data.ray_trace_depth;
+    let transmittance = self.get_light_transmittance(
+      tracer,
+      intersection_info,
+      ray,
+      sample.direction,
+      sample.distance,
+      medium,
+      self.render_shadow && !is_deepest,
+    );
+    sample.color = sample.color.multiply(transmittance);
+
+    let mut color = self.render_diffuse(tracer, current_color, intersection_info, ray, &sample, bsdf);
+    if !is_deepest {
+      if let Some(elem) = tracer.scene.get_shape(&intersection_info.element_id) {
+        color = self.render_highlights(color, elem, intersection_info, ray, &sample, bsdf);
+      }
+    }
+    color
+  }
+
+  // soft shadows: the light is averaged over stratified points on its surface, each with its own
+  // shadow ray.  the first hit gets area_light_samples of them, deeper ones a single sample.
+  #[allow(clippy::too_many_arguments)]
+  fn render_area_light(
+    &self,
+    tracer: &RayTracer,
+    current_color: ColorVector,
+    intersection_info: &IntersectionInfo,
+    ray: &Ray,
+    depth: u32,
+    light: &CompiledLight,
+    bsdf: Option<&Bsdf>,
+    medium: Option<HomogeneousMedium>,
+  ) -> ColorVector {
+    let elem = match tracer.scene.get_shape(&intersection_info.element_id) {
+      Some(elem) => elem,
+      None => return current_color,
+    };
+    let num_samples = if depth > 0 {
+      1
+    } else {
+      tracer.render_data.area_light_samples.max(1)
+    };
+
+    let mut light_color = ColorVector::new(0.0, 0.0, 0.0);
+    for i in 0..num_samples {
+      let (u1, u2) = sampling::stratified_sample(i, num_samples);
+      let mut sample = light.sample(intersection_info.position, u1, u2);
+      if sample.distance <= 0.0 {
+        continue;
+      }
+      let transmittance = self.get_light_transmittance(
+        tracer,
+        intersection_info,
+        ray,
+        sample.direction,
+        sample.distance,
+        medium,
+        self.render_shadow,
+      );
+      sample.color = sample.color.multiply(transmittance);
+      let black = ColorVector::new(0.0, 0.0, 0.0);
+      let diffuse = self.render_diffuse(tracer, black, intersection_info, ray, &sample, bsdf);
+      light_color = light_color.add(self.render_highlights(
+        diffuse,
+        elem,
+        intersection_info,
+        ray,
+        &sample,
+        bsdf,
+      ));
+    }
+    current_color.add(light_color.multiply_by_scalar(1.0 / num_samples as f64))
+  }
+
+  fn render_reflection(
+    &self,
+    tracer: &RayTracer,
+    current_color: ColorVector,
+    intersection_info: &IntersectionInfo,
+    ray: &Ray,
+    depth: u32,
+    medium: Option<HomogeneousMedium>,
+  ) -> ColorVector {
+    let mut color = current_color;
+
+    if self.render_reflection {
+      match tracer.scene.get_shape(&intersection_info.element_id) {
+        None => {}
+        Some(elem) => {
+          // transparent surfaces get their reflection from the fresnel term in render_refraction
+          let material = elem.get_material();
+          let coords = &intersection_info.coords;
+          if material.get_reflection(coords) > 0.0 && material.get_transparency(coords) <= 0.0 {
+            let reflection_ray = tracer.get_reflection_ray(
+              intersection_info.position,
+              intersection_info.normal,
+              ray.get_direction(),
+            );
+            let refl_color = self.trace_ray(tracer, &reflection_ray, elem.get_id(), depth, medium);
+            color = color.add(refl_color.multiply_by_scalar(material.get_reflection(coords)));
+          }
+        }
+      }
+    }
+
+    color
+  }
+
+  // follows the specular lobes of a bsdf with importance sampled rays.  only the first bounce
+  // gets the full glossy_samples, deeper bounces use one so the ray count doesn't explode.
+  // the environment seen by rough lobes is weighted against what render_environment adds for it.
+  #[allow(clippy::too_many_arguments)]
+  fn render_glossy_reflection(
+    &self,
+    tracer: &RayTracer,
+    current_color: ColorVector,
+    intersection_info: &IntersectionInfo,
+    ray: &Ray,
+    depth: u32,
+    bsdf: &Bsdf,
+    medium: Option<HomogeneousMedium>,
+  ) -> ColorVector {
+    let mut color = current_color;
+
+    if self.render_reflection {
+      let n = intersection_info.normal;
+      let wo = ray.get_direction().multiply_by_scalar(-1.0);
+      let environment_samples = self.get_environment_samples(tracer, depth);
+      for lobe in bsdf.get_specular_lobes() {
+        let num_samples = self.get_glossy_samples(tracer, &lobe, depth);
+
+        let mut lobe_color = ColorVector::new(0.0, 0.0, 0.0);
+        for _ in 0..num_samples {
+          if let Some(sample) = lobe.sample(n, wo, sampling::next_f64(), sampling::next_f64()) {
+            let glossy_ray = tracer.offset_ray(
+              intersection_info.position,
+              intersection_info.geometric_normal,
+              sample.direction,
+            );
+            let background_weight = match tracer.scene.environment {
+              Some(ref environment) if !lobe.is_specular() => sampling::power_heuristic(
+                num_samples as f64 * lobe.pdf(n, wo, sample.direction),
+                environment_samples as f64 * environment.pdf(sample.direction),
+              ),
+              _ => 1.0,
+            };
+            let glossy_color =
+              self.get_ray_color(tracer, &glossy_ray, 0, depth + 1, medium, background_weight);
+            lobe_color = lobe_color.add(glossy_color.multiply(sample.weight));
+          }
+        }
+        color = color.add(lobe_color.multiply_by_scalar(1.0 / num_samples as f64));
+      }
+    }
+
+    color
+  }
+
+  fn render_refraction(
+    &self,
+    tracer: &RayTracer,
+    current_color: ColorVector,
+    intersection_info: &IntersectionInfo,
+    ray: &Ray,
+    depth: u32,
+    medium: Option<HomogeneousMedium>,
+  ) -> ColorVector {
+    let mut color = current_color;
+
+    if self.render_refraction {
+      match tracer.scene.get_shape(&intersection_info.element_id) {
+        None => {}
+        Some(elem) => {
+          let material = elem.get_material();
+          let coords = &intersection_info.coords;
+          if material.get_transparency(coords) > 0.0 {
+            // the outside of every shape is assumed to be air
+            let ior = spectral::get_refraction(&*material, coords).max(1.0);
+            let v = ray.get_direction();
+            // which side we're on comes from the geometry, the bent shading normal only shapes the rays
+            let gn = intersection_info.geometric_normal;
+            let entering = v.dot_product(gn) < 0.0;
+            let (n, n1, n2) = if entering {
+              (intersection_info.normal, 1.0, ior)
+            } else {
+              (intersection_info.normal.multiply_by_scalar(-1.0), ior, 1.0)
+            };
+            let cos_i = -(n.dot_product(v));
+
+            let reflectance = tracer.get_fresnel_reflectance(cos_i.max(0.0), n1, n2);
+            let reflection_ray = tracer.get_reflection_ray(intersection_info.position, n, v);
+            let reflection_ray = tracer.offset_ray(
+              reflection_ray.get_position(),
+              gn,
+              reflection_ray.get_direction(),
+            );
+            let refl_color = self.trace_ray(tracer, &reflection_ray, 0, depth, medium);
+
+            let dielectric_color = match tracer.get_refraction_direction(n, v, n1 / n2) {
+              Some(t) if reflectance < 1.0 => {
+                let refraction_ray = tracer.offset_ray(intersection_info.position, gn, t);
+                // the refracted ray is inside the shape now, or back outside of all shapes
+                let inner_medium = if entering {
+                  material.get_medium()
+                } else {
+                  tracer.get_scene_medium()
+                };
+                let refr_color = self.trace_ray(tracer, &refraction_ray, 0, depth, inner_medium);
+                refl_color
+                  .multiply_by_scalar(reflectance)
+                  .add(refr_color.multiply_by_scalar(1.0 - reflectance))
+              }
+              _ => refl_color, // total internal reflection
+            };
+
+            color = color.add(dielectric_color.multiply_by_scalar(material.get_transparency(coords)));
+          }
+        }
+      }
+    }
+    color
+  }
+
+  fn render_highlights(
+    &self,
+    current_color: ColorVector,
+    elem: &CompiledShape,
+    intersection_info: &IntersectionInfo,
+    ray: &Ray,
+    light: &LightSample,
+    bsdf: Option<&Bsdf>,
+  ) -> ColorVector {
+    let mut color = current_color;
+    let material = elem.get_material();
+    let coords = &intersection_info.coords;
+    // a bsdf already includes its specular response in render_diffuse
+    if self.render_highlights && bsdf.is_none()
+      && material.get_specular(coords) > 0.0
+    {
+      // blinn-phong: the half vector between the light and the viewer, both as seen from the hit point.
+      let lv = light.direction;
+      let e = ray.get_direction().multiply_by_scalar(-1.0);
+      let h = lv.add(e).normalize();
+      let n_dot_h = intersection_info.normal.dot_product(h);
+      if n_dot_h > 0.0 && intersection_info.normal.dot_product(lv) > 0.0 {
+        let gloss_weight = material.get_specular(coords) * n_dot_h.powf(material.get_shininess(coords));
+        color = color.add(light.color.multiply_by_scalar(gloss_weight));
+      }
+    }
+    color
+  }
+
+  // medium is the one the ray arrived through, reflected rays stay in it
+  fn ray_trace(
+    &self,
+    tracer: &RayTracer,
+    intersection_info: &IntersectionInfo,
+    ray: &Ray,
+    depth: u32,
+    medium: Option<HomogeneousMedium>,
+  ) -> ColorVector {
+    let mut ambience = tracer.scene.background.ambience;
+    if tracer.render_data.ambient_occlusion && ambience > 0.0 {
+      ambience *= tracer.get_ambient_occlusion(intersection_info, ray);
+    }
+    let mut color = intersection_info.color.multiply_by_scalar(ambience);
+
+    // foreach light
+    // rust note:  need the & since we want references to the items in the iteration.
+    // by default, for loops use into_iter<> which is a MOVE
+    // http://hermanradtke.com/2015/06/22/effectively-using-iterators-in-rust.html
+    let (bsdf, emission) = match tracer.scene.get_shape(&intersection_info.element_id) {
+      Some(elem) => {
+        let material = elem.get_material();
+        (
+          material.get_bsdf(&intersection_info.coords),
+          material.get_emission(&intersection_info.coords),
+        )
+      }
+      None => (None, ColorVector::new(0.0, 0.0, 0.0)),
+    };
+    color = color.add(emission);
+
+    let black = ColorVector::new(0.0, 0.0, 0.0);
+    for (light, weight) in tracer.pick_lights(intersection_info.position, intersection_info.normal) {
+      let light_color =
+        self.render_light(tracer, black, intersection_info, ray, depth, light, bsdf.as_ref(), medium);
+      color = color.add(light_color.multiply_by_scalar(weight));
+    }
+
+    color = self.render_emitters(tracer, color, intersection_info, ray, depth, bsdf.as_ref(), medium);
+    color = self.render_environment(tracer, color, intersection_info, ray, depth, bsdf.as_ref(), medium);
+
+    // secondary rays are traced once per hit, not once per light
+    if depth < tracer.render_data.ray_trace_depth {
+      color = self.render_reflection(tracer, color, intersection_info, ray, depth, medium);
+      color = self.render_refraction(tracer, color, intersection_info, ray, depth, medium);
+      if let Some(ref bsdf) = bsdf {
+        color =
+          self.render_glossy_reflection(tracer, color, intersection_info, ray, depth, bsdf, medium);
+      }
+    }
+
+    color
+  }
+}
+
+impl Default for WhittedIntegrator {
+  fn default() -> WhittedIntegrator {
+    WhittedIntegrator::new()
+  }
+}
+
+impl Integrator for WhittedIntegrator {
+  fn get_radiance(&self, tracer: &RayTracer, ray: &Ray) -> ColorVector {
+    // the camera is assumed to be outside of all shapes
+    self.get_ray_color(tracer, ray, 0, 0, tracer.get_scene_medium(), 1.0)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::Arc;
+
+  use camera::Camera;
+  use light::{Light, PointLight};
+  use material::{Material, PhongMaterial};
+  use renderer::RenderData;
+  use scene::{Background, Scene};
+  use shapes::{PlaneShape, Shape};
+
+  // what the camera sees of a plane facing it, in front of a gray background, lit by a point light.
+  // the reflected and transmitted rays only see the background.
+  fn get_color(material: PhongMaterial, whitted: WhittedIntegrator) -> ColorVector {
+    let plane: Box<dyn Shape> = Box::new(PlaneShape {
+      position: PosVector::new(0.0, 0.0, 1.0),
+      d_val: 0.0,
+      material: Arc::new(material) as Arc<dyn Material>,
+      id: 1,
+    });
+    let white = ColorVector::new(1.0, 1.0, 1.0);
+    let light: Box<dyn Light> = Box::new(PointLight::new(PosVector::new(1.0, 2.0, 5.0), white));
+    let background = Background::new(ColorVector::new(0.25, 0.5, 0.75), 0.0);
+    let scene = Scene::new(background, vec![plane], vec![light]);
+    let camera = Camera::new(
+      PosVector::new(0.0, -3.0, 4.0),
+      PosVector::new(0.0, 0.0, 0.0),
+      PosVector::new(0.0, 0.0, 1.0),
+      45.0,
+    );
+    let ray = camera.get_ray(0.0, 0.0);
+    let tracer = RayTracer::new(camera, RenderData::new(1, 1, 5, 1, false), Arc::new(scene));
+    whitted.get_radiance(&tracer, &ray)
+  }
+
+  fn get_local_color(material: PhongMaterial) -> ColorVector {
+    let mut whitted = WhittedIntegrator::new();
+    whitted.render_reflection = false;
+    whitted.render_refraction = false;
+    get_color(material, whitted)
+  }
+
+  fn assert_close(a: ColorVector, b: ColorVector) {
+    let diff = (a.r - b.r).abs().max((a.g - b.g).abs()).max((a.b - b.b).abs());
+    assert!(diff < 1e-9, "{:?} != {:?}", a, b);
+  }
+
+  #[test]
+  fn reflection_adds_to_the_local_color() {
+    let background = ColorVector::new(0.25, 0.5, 0.75);
+    // kd + ks is more than 1 on purpose
+    let material = PhongMaterial::new(ColorVector::new(0.8, 0.4, 0.2), 0.7, 0.6, 30.0, 0.0, 1.0);
+    let local = get_local_color(material);
+    assert!(local.r > 0.0);
+    let color = get_color(material, WhittedIntegrator::new());
+    assert_close(color, local.add(background.multiply_by_scalar(0.6)));
+  }
+
+  #[test]
+  fn transmission_adds_to_the_local_color() {
+    let background = ColorVector::new(0.25, 0.5, 0.75);
+    // an index of 1 refracts straight through the plane, with no fresnel reflection
+    let material = PhongMaterial::new(ColorVector::new(0.8, 0.4, 0.2), 0.5, 0.3, 30.0, 0.9, 1.0);
+    let local = get_local_color(material);
+    assert!(local.r > 0.0);
+    let color = get_color(material, WhittedIntegrator::new());
+    assert_close(color, local.add(background.multiply_by_scalar(0.9)));
+  }
+}
@@ -73,8 +73,13 @@ fn render_nff(filename_no_ext: &str) {
     println!("preparing to render nff: {}", filename_no_ext);
 
     let nff_path = format!("nff/{}.nff", filename_no_ext);
-    let parse_result =
-        nffparsing::parse_nff_file(&nff_path, num_cpus::get() as u32, 5);
+    let parse_result = match nffparsing::parse_nff_file(&nff_path, num_cpus::get() as u32, 5) {
+        Ok(parse_result) => parse_result,
+        Err(err) => {
+            println!("can't parse {}: {}", nff_path, err);
+            return;
+        }
+    };
 
     let (elapsed, _) = measure_time(|| {
         let scene_path = format!("output/render_{}.png", filename_no_ext);